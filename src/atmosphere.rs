@@ -0,0 +1,354 @@
+// Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
+
+use std::f64::consts::{FRAC_PI_2, PI};
+use crate::vec3::Vec3;
+use crate::color::Color;
+use crate::phase::PhaseFunction;
+
+// Sea level coefficients per kilometre for the red, green and blue primaries.
+const RAYLEIGH_SCATTERING: [f64; 3] = [5.802e-3, 13.558e-3, 33.1e-3];
+const MIE_SCATTERING: f64 = 3.996e-3;
+const MIE_EXTINCTION: f64 = 4.440e-3;
+const OZONE_ABSORPTION: [f64; 3] = [0.650e-3, 1.881e-3, 0.085e-3];
+const OZONE_CENTER: f64 = 25.0;
+const OZONE_HALF_WIDTH: f64 = 15.0;
+
+/// Height of the viewer above the ground in kilometres, enough for horizontal rays to clear the planet.
+const OBSERVER_ALTITUDE: f64 = 0.01;
+
+const VIEW_STEPS: usize = 32;
+const LIGHT_STEPS: usize = 16;
+const TABLE_ELEVATIONS: usize = 64;
+const TABLE_AZIMUTHS: usize = 32;
+const MULTIPLE_HEIGHTS: usize = 32;
+const MULTIPLE_DIRECTIONS: usize = 64;
+const MULTIPLE_STEPS: usize = 16;
+
+/// Shape and composition of the atmosphere. Distances are in kilometres.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AtmosphereParameters {
+    pub planet_radius: f64,
+    pub atmosphere_height: f64,
+    pub rayleigh_scale_height: f64,
+    pub mie_scale_height: f64,
+    /// Amount of aerosols: 1 is perfectly clean air, 2 a clear day, higher values give a hazy, milky sky.
+    pub turbidity: f64,
+    /// Asymmetry of aerosol scattering, which controls the size of the glow around the sun.
+    pub mie_g: f64,
+    pub ozone: bool,
+    /// Approximates light scattered more than once, which brightens the sky and keeps twilight from going black.
+    pub multiple_scattering: bool,
+}
+
+impl Default for AtmosphereParameters {
+    fn default() -> Self {
+        AtmosphereParameters {
+            planet_radius: 6360.0,
+            atmosphere_height: 100.0,
+            rayleigh_scale_height: 8.0,
+            mie_scale_height: 1.2,
+            turbidity: 2.0,
+            mie_g: 0.8,
+            ozone: true,
+            multiple_scattering: true,
+        }
+    }
+}
+
+/// Sky light from a Rayleigh, Mie and ozone atmosphere lit by a distant sun. The light scattered toward a viewer
+/// on the ground is integrated once for a table of directions, since the sun does not move during a render.
+#[derive(Debug, Clone)]
+pub struct Atmosphere {
+    parameters: AtmosphereParameters,
+    sun_direction: Vec3,
+    sun_azimuth: f64,
+    sun_transmittance: Color,
+    mie_phase: PhaseFunction,
+    /// Rayleigh, Mie and multiple scattering by view elevation and azimuth from the sun, per unit of sun irradiance.
+    /// The first two are stored without their phase functions, which only depend on the angle to the sun.
+    table: Vec<[Color; 3]>,
+}
+
+impl Atmosphere {
+    pub fn new(parameters: AtmosphereParameters, sun_direction: Vec3) -> Self {
+        let mut atmosphere = Atmosphere {
+            parameters,
+            sun_direction,
+            sun_azimuth: sun_direction.z.atan2(sun_direction.x),
+            sun_transmittance: Color::new(0.0, 0.0, 0.0),
+            mie_phase: PhaseFunction::CornetteShanks { g: parameters.mie_g },
+            table: Vec::with_capacity(TABLE_ELEVATIONS * TABLE_AZIMUTHS),
+        };
+
+        atmosphere.sun_transmittance = atmosphere.transmittance(atmosphere.observer(), sun_direction);
+
+        let multiple = if parameters.multiple_scattering {
+            atmosphere.multiple_scattering_table()
+        } else {
+            Vec::new()
+        };
+
+        for i in 0..TABLE_ELEVATIONS {
+            // Squared spacing puts more rows near the horizon, where the sky changes fastest.
+            let elevation = (i as f64 / (TABLE_ELEVATIONS - 1) as f64).powi(2) * FRAC_PI_2;
+
+            for j in 0..TABLE_AZIMUTHS {
+                let azimuth = atmosphere.sun_azimuth + j as f64 / (TABLE_AZIMUTHS - 1) as f64 * PI;
+                let direction = Vec3::new(elevation.cos() * azimuth.cos(), elevation.sin(), elevation.cos() * azimuth.sin());
+
+                let entry = atmosphere.integrate(direction, &multiple);
+                atmosphere.table.push(entry);
+            }
+        }
+
+        atmosphere
+    }
+
+    /// Fraction of sunlight that reaches the ground through the atmosphere, which reddens the sun near the horizon.
+    pub fn sun_transmittance(&self) -> Color {
+        self.sun_transmittance
+    }
+
+    /// Light scattered toward the viewer from `direction`, per unit of sun irradiance. Directions below the horizon
+    /// mirror those above it, as the gradient sky does.
+    pub fn get_sky_color(&self, direction: Vec3) -> Color {
+        let elevation = direction.y.abs().min(1.0).asin();
+        let mut azimuth = (direction.z.atan2(direction.x) - self.sun_azimuth).rem_euclid(2.0 * PI);
+        if azimuth > PI {
+            azimuth = 2.0 * PI - azimuth;
+        }
+
+        let row = (elevation / FRAC_PI_2).sqrt() * (TABLE_ELEVATIONS - 1) as f64;
+        let column = azimuth / PI * (TABLE_AZIMUTHS - 1) as f64;
+        // At the last row or column the cell before it is used with a fraction of 1.
+        let i = (row as usize).min(TABLE_ELEVATIONS - 2);
+        let j = (column as usize).min(TABLE_AZIMUTHS - 2);
+        let (di, dj) = (row - i as f64, column - j as f64);
+
+        let entry = |k: usize| {
+            let top = self.table[i * TABLE_AZIMUTHS + j][k].mix(self.table[i * TABLE_AZIMUTHS + j + 1][k], dj);
+            let bottom = self.table[(i + 1) * TABLE_AZIMUTHS + j][k].mix(self.table[(i + 1) * TABLE_AZIMUTHS + j + 1][k], dj);
+
+            top.mix(bottom, di)
+        };
+
+        let cos_theta = direction.dot(self.sun_direction);
+        let rayleigh_phase = 3.0 / (16.0 * PI) * (1.0 + cos_theta * cos_theta);
+
+        entry(0) * rayleigh_phase + entry(1) * self.mie_phase.evaluate(cos_theta) + entry(2)
+    }
+
+    /// Integrates single scattering, and multiple scattering when `multiple` is not empty, along a view ray.
+    fn integrate(&self, direction: Vec3, multiple: &[Color]) -> [Color; 3] {
+        let origin = self.observer();
+
+        let mut depth = Color::new(0.0, 0.0, 0.0);
+        let mut rayleigh = Color::new(0.0, 0.0, 0.0);
+        let mut mie = Color::new(0.0, 0.0, 0.0);
+        let mut scattered = Color::new(0.0, 0.0, 0.0);
+
+        for (t, dt) in steps(self.ray_length(origin, direction), VIEW_STEPS) {
+            let position = origin + direction * t;
+            let height = self.height(position);
+            let extinction = self.extinction(height);
+
+            let view_transmittance = (depth + extinction * (dt / 2.0)).map(|x| (-x).exp());
+            depth = depth + extinction * dt;
+
+            let light = view_transmittance * self.transmittance(position, self.sun_direction);
+            let (rayleigh_density, mie_density, _) = self.densities(height);
+
+            rayleigh = rayleigh + light * (rayleigh_density * dt);
+            mie = mie + light * (mie_density * dt);
+
+            if !multiple.is_empty() {
+                scattered = scattered + view_transmittance * self.scattering(height) * lookup_multiple(multiple, height / self.parameters.atmosphere_height) * dt;
+            }
+        }
+
+        [rayleigh * rgb(RAYLEIGH_SCATTERING), mie * (MIE_SCATTERING * self.aerosols()), scattered]
+    }
+
+    /// Light scattered two or more times per unit of scattering coefficient, by height. This is the isotropic
+    /// approximation from Hillaire's "A Scalable and Production Ready Sky and Atmosphere Rendering Technique":
+    /// the second order is integrated over the sphere of directions and every further order is assumed to
+    /// transfer the same fraction of light again, which sums to a geometric series.
+    fn multiple_scattering_table(&self) -> Vec<Color> {
+        let mut table = Vec::with_capacity(MULTIPLE_HEIGHTS);
+
+        for k in 0..MULTIPLE_HEIGHTS {
+            let height = (k as f64 / (MULTIPLE_HEIGHTS - 1) as f64).powi(2) * self.parameters.atmosphere_height;
+            let origin = Vec3::new(0.0, self.parameters.planet_radius + height.max(OBSERVER_ALTITUDE), 0.0);
+
+            let mut second_order = Color::new(0.0, 0.0, 0.0);
+            let mut transfer = Color::new(0.0, 0.0, 0.0);
+
+            for direction in sphere_directions(MULTIPLE_DIRECTIONS) {
+                let mut depth = Color::new(0.0, 0.0, 0.0);
+
+                for (t, dt) in steps(self.ray_length(origin, direction), MULTIPLE_STEPS) {
+                    let position = origin + direction * t;
+                    let extinction = self.extinction(self.height(position));
+
+                    let view_transmittance = (depth + extinction * (dt / 2.0)).map(|x| (-x).exp());
+                    depth = depth + extinction * dt;
+
+                    let scattered = view_transmittance * self.scattering(self.height(position)) * dt;
+
+                    second_order = second_order + scattered * self.transmittance(position, self.sun_direction);
+                    transfer = transfer + scattered;
+                }
+            }
+
+            // Both integrals use the isotropic phase function 1 / (4 pi) and average over the directions.
+            let second_order = second_order / (4.0 * PI * MULTIPLE_DIRECTIONS as f64);
+            let transfer = transfer / MULTIPLE_DIRECTIONS as f64;
+
+            let [l_r, l_g, l_b] = second_order.to_array();
+            let [f_r, f_g, f_b] = transfer.to_array();
+            table.push(Color::new(l_r / (1.0 - f_r), l_g / (1.0 - f_g), l_b / (1.0 - f_b)));
+        }
+
+        table
+    }
+
+    fn transmittance(&self, origin: Vec3, direction: Vec3) -> Color {
+        if self.hits_ground(origin, direction) {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        let mut depth = Color::new(0.0, 0.0, 0.0);
+        for (t, dt) in steps(self.ray_length(origin, direction), LIGHT_STEPS) {
+            depth = depth + self.extinction(self.height(origin + direction * t)) * dt;
+        }
+
+        depth.map(|x| (-x).exp())
+    }
+
+    fn observer(&self) -> Vec3 {
+        Vec3::new(0.0, self.parameters.planet_radius + OBSERVER_ALTITUDE, 0.0)
+    }
+
+    fn height(&self, position: Vec3) -> f64 {
+        (position.mag() - self.parameters.planet_radius).max(0.0)
+    }
+
+    fn aerosols(&self) -> f64 {
+        (self.parameters.turbidity - 1.0).max(0.0)
+    }
+
+    /// Relative densities of air molecules, aerosols and ozone at a height.
+    fn densities(&self, height: f64) -> (f64, f64, f64) {
+        let ozone = if self.parameters.ozone {
+            (1.0 - (height - OZONE_CENTER).abs() / OZONE_HALF_WIDTH).max(0.0)
+        } else {
+            0.0
+        };
+
+        ((-height / self.parameters.rayleigh_scale_height).exp(), (-height / self.parameters.mie_scale_height).exp(), ozone)
+    }
+
+    fn scattering(&self, height: f64) -> Color {
+        let (rayleigh, mie, _) = self.densities(height);
+
+        rgb(RAYLEIGH_SCATTERING) * rayleigh + Color::new(1.0, 1.0, 1.0) * (MIE_SCATTERING * self.aerosols() * mie)
+    }
+
+    fn extinction(&self, height: f64) -> Color {
+        let (rayleigh, mie, ozone) = self.densities(height);
+
+        rgb(RAYLEIGH_SCATTERING) * rayleigh + Color::new(1.0, 1.0, 1.0) * (MIE_EXTINCTION * self.aerosols() * mie) + rgb(OZONE_ABSORPTION) * ozone
+    }
+
+    fn hits_ground(&self, origin: Vec3, direction: Vec3) -> bool {
+        matches!(intersect(origin, direction, self.parameters.planet_radius), Some((near, _)) if near > 0.0)
+    }
+
+    /// Distance along a ray to the ground or, if it misses the ground, to the top of the atmosphere.
+    fn ray_length(&self, origin: Vec3, direction: Vec3) -> f64 {
+        match intersect(origin, direction, self.parameters.planet_radius) {
+            Some((near, _)) if near > 0.0 => near,
+            _ => match intersect(origin, direction, self.parameters.planet_radius + self.parameters.atmosphere_height) {
+                Some((_, far)) => far.max(0.0),
+                None => 0.0,
+            },
+        }
+    }
+}
+
+fn rgb(values: [f64; 3]) -> Color {
+    Color::new(values[0], values[1], values[2])
+}
+
+/// Distances to a sphere around the origin along a ray, nearest first.
+fn intersect(origin: Vec3, direction: Vec3, radius: f64) -> Option<(f64, f64)> {
+    let b = origin.dot(direction);
+    let discriminant = b * b - (origin.mag_squared() - radius * radius);
+
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let root = discriminant.sqrt();
+    Some((-b - root, -b + root))
+}
+
+/// Sample positions and lengths along a ray, packed quadratically toward its start where the air is densest.
+fn steps(length: f64, count: usize) -> impl Iterator<Item = (f64, f64)> {
+    let n2 = (count * count) as f64;
+
+    (0..count).map(move |i| {
+        let i = i as f64;
+
+        (length * (i + 0.5) * (i + 0.5) / n2, length * (2.0 * i + 1.0) / n2)
+    })
+}
+
+/// Evenly spread directions on the unit sphere, along a Fibonacci spiral.
+fn sphere_directions(count: usize) -> impl Iterator<Item = Vec3> {
+    let golden_angle = PI * (3.0 - 5.0f64.sqrt());
+
+    (0..count).map(move |i| {
+        let y = 1.0 - (i as f64 + 0.5) / count as f64 * 2.0;
+        let radius = (1.0 - y * y).sqrt();
+        let angle = golden_angle * i as f64;
+
+        Vec3::new(radius * angle.cos(), y, radius * angle.sin())
+    })
+}
+
+fn lookup_multiple(table: &[Color], fraction: f64) -> Color {
+    let position = fraction.clamp(0.0, 1.0).sqrt() * (table.len() - 1) as f64;
+    let index = (position as usize).min(table.len() - 2);
+
+    table[index].mix(table[index + 1], position - index as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_reaches_the_last_table_entries() {
+        let parameters = AtmosphereParameters { multiple_scattering: false, ..AtmosphereParameters::default() };
+        let sun_direction = Vec3::new(0.0, 0.5, 0.75f64.sqrt());
+        let atmosphere = Atmosphere::new(parameters, sun_direction);
+
+        let expected = |i: usize, j: usize, direction: Vec3| {
+            let entry = atmosphere.table[i * TABLE_AZIMUTHS + j];
+            let cos_theta = direction.dot(sun_direction);
+
+            entry[0] * (3.0 / (16.0 * PI) * (1.0 + cos_theta * cos_theta)) + entry[1] * atmosphere.mie_phase.evaluate(cos_theta) + entry[2]
+        };
+
+        // Straight up is the last row, and on the horizon straight away from the sun the last column.
+        let zenith = Vec3::new(0.0, 1.0, 0.0);
+        let away = Vec3::new(0.0, 0.0, -1.0);
+
+        for (direction, expected) in [(zenith, expected(TABLE_ELEVATIONS - 1, 0, zenith)), (away, expected(0, TABLE_AZIMUTHS - 1, away))] {
+            for (value, expected) in atmosphere.get_sky_color(direction).to_array().iter().zip(expected.to_array()) {
+                assert!((value - expected).abs() <= expected.abs() * 1e-9, "{} instead of {}", value, expected);
+            }
+        }
+    }
+}
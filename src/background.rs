@@ -1,47 +1,204 @@
 // Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
 
+use std::f64::consts::PI;
 use crate::vec3::Vec3;
 use crate::color::Color;
+use crate::atmosphere::{Atmosphere, AtmosphereParameters};
+use crate::preetham::Preetham;
+use crate::hosek_wilkie::{HosekWilkie, HosekWilkieData};
+use crate::night::NightSky;
+
+/// How the color of the sky is computed, as chosen by the `sky_model` configuration key.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SkyModel {
+    /// `sky_colors` interpolated by distance from the sun.
+    Gradient,
+    /// Rayleigh, Mie and ozone scattering through the atmosphere.
+    Atmosphere,
+    /// The Preetham analytic daylight model.
+    Preetham,
+    /// The Hosek-Wilkie analytic sky model, with coefficients read from the reference data file.
+    HosekWilkie,
+}
+
+impl SkyModel {
+    pub fn from_name(name: &str) -> Option<SkyModel> {
+        match name {
+            "gradient" => Some(SkyModel::Gradient),
+            "atmosphere" => Some(SkyModel::Atmosphere),
+            "preetham" => Some(SkyModel::Preetham),
+            "hosek_wilkie" => Some(SkyModel::HosekWilkie),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Sky {
+    Gradient(Vec<Color>),
+    Atmosphere { atmosphere: Atmosphere, irradiance: Color },
+    Preetham { model: Preetham, irradiance: Color },
+    HosekWilkie { model: HosekWilkie, irradiance: Color },
+}
+
+/// A distant light source with a small disk, such as the sun or the moon.
+#[derive(Debug, Copy, Clone)]
+pub struct Light {
+    pub direction: Vec3,
+    /// Average color of the disk.
+    pub color: Color,
+    pub solid_angle: f64,
+}
 
 #[derive(Debug, Clone)]
 pub struct Background {
     sun_size: f64,
     sun_color: Color,
-    sky_colors: Vec<Color>,
+    sky: Sky,
     ground_color: Color,
     sun_x: f64,
     sun_y: f64,
     sun_z: f64,
+    night: Option<NightSky>,
+    moon_light: Option<Light>,
+    /// How much of the night sky shows through, from `NightSky::darkness`.
+    darkness: f64,
 }
 
 impl Background {
-    pub fn new(sun_size: f64, sun_color: Color, sky_colors: Vec<Color>, ground_color: Color, phi: f64, theta: f64) -> Self {
-        let sun_x = phi.sin() * theta.cos();
-        let sun_y = phi.sin() * theta.sin();
-        let sun_z = phi.cos();
+    pub fn new(sun_size: f64, sun_color: Color, sky_colors: Vec<Color>, ground_color: Color, sun_direction: Vec3) -> Self {
+        let sun_direction = sun_direction.normalize();
+
+        Background { sun_size, sun_color, sky: Sky::Gradient(sky_colors), ground_color, sun_x: sun_direction.x, sun_y: sun_direction.y, sun_z: sun_direction.z, night: None, moon_light: None, darkness: 0.0 }
+    }
+
+    /// Replaces the sky gradient with light scattered by the atmosphere. The sun is dimmed and reddened by the air
+    /// in front of it, and `intensity` scales the sky relative to the sun disk.
+    pub fn with_atmosphere(mut self, parameters: AtmosphereParameters, intensity: f64) -> Self {
+        let atmosphere = Atmosphere::new(parameters, self.sun_direction());
+        let irradiance = self.sun_color * intensity;
+
+        self.sun_color = self.sun_color * atmosphere.sun_transmittance();
+        self.sky = Sky::Atmosphere { atmosphere, irradiance };
+
+        self
+    }
+
+    /// Replaces the sky gradient with the Preetham model. The sun keeps its configured color.
+    pub fn with_preetham(mut self, turbidity: f64, intensity: f64) -> Self {
+        let model = Preetham::new(turbidity, self.sun_direction());
+        self.sky = Sky::Preetham { model, irradiance: self.sun_color * intensity };
+
+        self
+    }
+
+    /// Replaces the sky gradient with the Hosek-Wilkie model. The sun keeps its configured color.
+    pub fn with_hosek_wilkie(mut self, data: &HosekWilkieData, turbidity: f64, ground_albedo: f64, intensity: f64) -> Self {
+        let model = HosekWilkie::new(data, turbidity, ground_albedo, self.sun_direction());
+        self.sky = Sky::HosekWilkie { model, irradiance: self.sun_color * intensity };
+
+        self
+    }
+
+    /// Adds the moon, stars and Milky Way. They fade in over the day sky as the sun sets.
+    pub fn with_night(mut self, night: NightSky) -> Self {
+        self.darkness = NightSky::darkness(self.sun_direction());
+        self.moon_light = Some(Light { direction: night.moon_direction(), color: night.moon_light(self.sun_direction()), solid_angle: night.moon_solid_angle() });
+        self.night = Some(night);
+
+        self
+    }
 
-        Background { sun_size, sun_color, sky_colors, ground_color, sun_x, sun_y, sun_z }
+    pub fn sun_direction(&self) -> Vec3 {
+        Vec3::new(self.sun_x, self.sun_y, self.sun_z)
+    }
+
+    pub fn sun_color(&self) -> Color {
+        self.sun_color
+    }
+
+    /// Solid angle of the sun disk. `sun_size` is the chord length between the sun center and its edge
+    /// on the unit sphere, so the disk is a spherical cap with 1 - cos(radius) = sun_size^2 / 2.
+    pub fn sun_solid_angle(&self) -> f64 {
+        PI * self.sun_size * self.sun_size
+    }
+
+    /// The sun and, at night, the moon. Both light the clouds directly.
+    pub fn lights(&self) -> impl Iterator<Item = Light> + '_ {
+        let sun = Light { direction: self.sun_direction(), color: self.sun_color, solid_angle: self.sun_solid_angle() };
+
+        std::iter::once(sun).chain(self.moon_light)
     }
 
     pub fn get_background_color(&self, direction: Vec3) -> Color {
         if direction.y < 0.0 {
-            return self.ground_color;
+            return self.get_ground_color();
         }
 
         self.get_sky_color(direction)
     }
 
+    /// Like `get_background_color`, but the sun and moon disks are replaced by the sky behind them. Used by paths
+    /// whose direct light has already been added by light sampling, so it is not counted twice. The stars and the
+    /// Milky Way are left out too, as their light on the clouds is negligible and costly to look up.
+    pub fn get_background_color_without_lights(&self, direction: Vec3) -> Color {
+        if direction.y < 0.0 {
+            return self.get_ground_color();
+        }
+
+        self.get_sky(direction, self.get_sun_distance(direction).max(self.sun_size), false)
+    }
+
     pub fn get_sky_color(&self, direction: Vec3) -> Color {
-        let dist = ((direction.x - self.sun_x).powi(2) + (direction.y - self.sun_y).powi(2) + (direction.z - self.sun_z).powi(2)).sqrt();
+        let dist = self.get_sun_distance(direction);
 
-        if dist <= self.sun_size {
+        // Once the sun has set the ground hides it.
+        if dist <= self.sun_size && self.sun_y >= 0.0 {
             return self.sun_color;
         }
 
-        let temp = (dist - self.sun_size) / (2.0 - self.sun_size) * (self.sky_colors.len() as f64 - 1.01);
-        let disk = temp as usize;
-        let disk_position = temp - temp.floor();
+        // The moon hides the stars behind it.
+        if let Some(moon) = self.night.as_ref().and_then(|night| night.get_moon_color(direction, self.sun_direction())) {
+            return moon + self.get_sky(direction, dist, false);
+        }
 
-        self.sky_colors[disk].mix(self.sky_colors[disk+1], disk_position)
+        self.get_sky(direction, dist, true)
     }
-}
\ No newline at end of file
+
+    /// The ground darkens to the night sky color as the sun sets.
+    fn get_ground_color(&self) -> Color {
+        match &self.night {
+            Some(night) => self.ground_color.mix(night.sky_color(), self.darkness),
+            None => self.ground_color,
+        }
+    }
+
+    fn get_sun_distance(&self, direction: Vec3) -> f64 {
+        ((direction.x - self.sun_x).powi(2) + (direction.y - self.sun_y).powi(2) + (direction.z - self.sun_z).powi(2)).sqrt()
+    }
+
+    /// The sky without the sun and moon disks. `dist` is the distance from the sun, at least `sun_size`.
+    fn get_sky(&self, direction: Vec3, dist: f64, stars: bool) -> Color {
+        let day = match &self.sky {
+            Sky::Gradient(sky_colors) => {
+                let temp = (dist - self.sun_size) / (2.0 - self.sun_size) * (sky_colors.len() as f64 - 1.01);
+                let disk = temp as usize;
+                let disk_position = temp - temp.floor();
+
+                sky_colors[disk].mix(sky_colors[disk+1], disk_position)
+            },
+            Sky::Atmosphere { atmosphere, irradiance } => *irradiance * atmosphere.get_sky_color(direction),
+            Sky::Preetham { model, irradiance } => *irradiance * model.get_sky_color(direction),
+            Sky::HosekWilkie { model, irradiance } => *irradiance * model.get_sky_color(direction),
+        };
+
+        match &self.night {
+            Some(night) if self.darkness > 0.0 => {
+                let night = if stars { night.get_sky_color(direction) } else { night.sky_color() };
+
+                day.mix(night, self.darkness)
+            },
+            _ => day,
+        }
+    }
+}
@@ -1,8 +1,17 @@
 // Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
 
+use std::f64::consts::PI;
 use crate::vec3::Vec3;
 use crate::ray3::Ray3;
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Lens {
+    /// A 90 degree pinhole camera, one per cube face.
+    Rectilinear,
+    /// Longitude along the image width and latitude along the height, centered on `forward`.
+    Equirectangular,
+}
+
 #[derive(Debug, Clone)]
 pub struct Camera {
     origin: Vec3,
@@ -12,6 +21,7 @@ pub struct Camera {
     dist_to_display: f64,
     width: f64,
     height: f64,
+    lens: Lens,
 }
 
 impl Camera {
@@ -23,16 +33,37 @@ impl Camera {
             up,
             dist_to_display: 1.0,
             width: 2.0,
-            height: 2.0
+            height: 2.0,
+            lens: Lens::Rectilinear,
+        }
+    }
+
+    /// A full 360 by 180 degree panorama camera. `forward` ends up in the middle of the image.
+    pub fn new_equirectangular(forward: Vec3, up: Vec3) -> Camera {
+        Camera {
+            lens: Lens::Equirectangular,
+            ..Camera::new(forward, up)
         }
     }
 
     pub fn get_ray(&self, x: f64, y:f64) -> Ray3 {
-        let x = (x - 0.5) * self.width;
-        let y = -(y - 0.5) * self.height;
+        match self.lens {
+            Lens::Rectilinear => {
+                let x = (x - 0.5) * self.width;
+                let y = -(y - 0.5) * self.height;
+
+                let ray_origin = self.origin + self.forward * self.dist_to_display + self.right * x + self.up * y;
+
+                Ray3::new(ray_origin, (ray_origin - self.origin).normalize())
+            },
+            Lens::Equirectangular => {
+                let longitude = (x - 0.5) * 2.0 * PI;
+                let latitude = -(y - 0.5) * PI;
 
-        let ray_origin = self.origin + self.forward * self.dist_to_display + self.right * x + self.up * y;
+                let direction = (self.forward * longitude.cos() + self.right * longitude.sin()) * latitude.cos() + self.up * latitude.sin();
 
-        Ray3::new(ray_origin, (ray_origin - self.origin).normalize())
+                Ray3::new(self.origin + direction * self.dist_to_display, direction)
+            },
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,154 @@
+// Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
+
+use std::path::PathBuf;
+use skybox::{Face, OutputTemplate, OverwritePolicy};
+
+pub const USAGE: &str = "\
+Usage: skybox [validate] [OPTIONS]
+
+Commands:
+    validate                       Check the configuration without rendering
+
+Options:
+    -c, --config <path>            Configuration file [default: config.ini]
+    -o, --output-dir <dir>         Directory the images are written to [default: output]
+    -t, --template <pattern>       Output file name [default: {face}.{ext}]
+                                   Placeholders: {face}, {ext}, {name} (config file name
+                                   without extension) and any configuration key, either
+                                   as {section.key} or, if only one section has it, {key}.
+                                   With --frames the default is {face}_{frame}.{ext}
+        --overwrite                Replace existing output files
+        --no-clobber               Skip faces whose output file already exists
+    -s, --set <section.key=value>  Override a configuration value, can be repeated
+    -f, --faces <face,...>         Faces to render (north, south, east, west, up, down)
+        --frames <count>           Render a sequence of frames numbered from 0000, each at
+                                   a later clouds.time
+        --time <start>..<end>      Times of the first and last frame [default: clouds.time
+                                   and one unit of time per frame]
+    -h, --help                     Print this message";
+
+#[derive(Debug)]
+pub struct Args {
+    pub validate_only: bool,
+    pub help: bool,
+    pub config_path: PathBuf,
+    pub output_dir: PathBuf,
+    pub template: OutputTemplate,
+    pub overwrite_policy: OverwritePolicy,
+    pub overrides: Vec<String>,
+    pub faces: Option<Vec<Face>>,
+    pub frames: Option<usize>,
+    pub time_range: Option<(f64, f64)>,
+}
+
+impl Args {
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Args, String> {
+        let mut result = Args {
+            validate_only: false,
+            help: false,
+            config_path: PathBuf::from("config.ini"),
+            output_dir: PathBuf::from("output"),
+            template: OutputTemplate::new(OutputTemplate::DEFAULT),
+            overwrite_policy: OverwritePolicy::Refuse,
+            overrides: Vec::new(),
+            faces: None,
+            frames: None,
+            time_range: None,
+        };
+        let mut template_set = false;
+
+        while let Some(arg) = args.next() {
+            // Accept both "--option value" and "--option=value".
+            let (option, inline_value) = match arg.split_once('=') {
+                Some((option, value)) if arg.starts_with("--") => (option.to_string(), Some(value.to_string())),
+                _ => (arg.clone(), None),
+            };
+
+            let mut value = || inline_value.clone().or_else(|| args.next()).ok_or(format!("missing value for {}", option));
+
+            match option.as_str() {
+                "validate" => result.validate_only = true,
+                "-h" | "--help" => result.help = true,
+                "-c" | "--config" => result.config_path = PathBuf::from(value()?),
+                "-o" | "--output-dir" => result.output_dir = PathBuf::from(value()?),
+                "-t" | "--template" => {
+                    result.template = OutputTemplate::new(&value()?);
+                    template_set = true;
+                },
+                "--overwrite" => result.overwrite_policy = OverwritePolicy::Overwrite,
+                "--no-clobber" => result.overwrite_policy = OverwritePolicy::Skip,
+                "-s" | "--set" => result.overrides.push(value()?),
+                "-f" | "--faces" => result.faces = Some(Args::parse_faces(&value()?)?),
+                "--frames" => result.frames = Some(Args::parse_frames(&value()?)?),
+                "--time" => result.time_range = Some(Args::parse_time_range(&value()?)?),
+                _ => return Err(format!("unexpected argument \"{}\"", arg)),
+            }
+        }
+
+        if result.time_range.is_some() && result.frames.is_none() {
+            return Err("--time needs --frames".to_string());
+        }
+        if result.frames.is_some() {
+            if !template_set {
+                result.template = OutputTemplate::new(OutputTemplate::FRAMES_DEFAULT);
+            } else if !result.template.has_placeholder("frame") {
+                return Err("--template needs a {frame} placeholder with --frames".to_string());
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn parse_faces(list: &str) -> Result<Vec<Face>, String> {
+        list.split(',')
+            .map(|name| Face::from_name(name.trim()).ok_or(format!("unknown face \"{}\"", name.trim())))
+            .collect()
+    }
+
+    fn parse_frames(count: &str) -> Result<usize, String> {
+        match count.trim().parse::<usize>() {
+            Ok(count) if count > 0 => Ok(count),
+            _ => Err(format!("invalid frame count \"{}\", expected a positive integer", count.trim())),
+        }
+    }
+
+    fn parse_time_range(range: &str) -> Result<(f64, f64), String> {
+        let invalid = || format!("invalid time range \"{}\", expected <start>..<end>", range.trim());
+
+        let (start, end) = range.split_once("..").ok_or_else(invalid)?;
+        let start = start.trim().parse::<f64>().map_err(|_| invalid())?;
+        let end = end.trim().parse::<f64>().map_err(|_| invalid())?;
+
+        Ok((start, end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn collects_overrides() {
+        let args = parse(&["--set", "clouds.cloud_seed=42", "-s", "images.format=hdr", "--set=sun.time=20:30"]).unwrap();
+        assert_eq!(args.overrides, ["clouds.cloud_seed=42", "images.format=hdr", "sun.time=20:30"]);
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        for args in [&["--set"][..], &["--sett", "a.b=c"], &["--faces", "north,nowhere"], &["--frames", "0"], &["--time", "0..1"], &["--frames", "2", "--time", "1"]] {
+            assert!(parse(args).is_err(), "{:?} was accepted", args);
+        }
+    }
+
+    #[test]
+    fn frames_need_a_frame_placeholder() {
+        assert!(parse(&["--frames", "3"]).unwrap().template.has_placeholder("frame"));
+        assert!(parse(&["--frames", "3", "-t", "{face}.{ext}"]).is_err());
+        assert!(parse(&["--frames", "3", "-t", "{frame}/{face}.{ext}"]).is_ok());
+        assert_eq!(parse(&["--frames", "3", "--time", "-1.5..2"]).unwrap().time_range, Some((-1.5, 2.0)));
+    }
+}
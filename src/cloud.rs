@@ -1,53 +1,350 @@
 // Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
 
 use crate::vec3::Vec3;
+use crate::height_profile::HeightProfile;
+use crate::coverage::Coverage;
+use crate::fast_rng::hash_i32;
+use crate::error::SkyboxError;
+
+/// The noise summed over the octaves of a cloud layer, as chosen by the `noise_type` configuration key.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NoiseType {
+    /// Random values at the grid corners with a quintic fade between them. Blobby and somewhat grid-aligned.
+    Value,
+    /// Gradient noise, which has smoother and less grid-aligned shapes than value noise.
+    Perlin,
+    /// Inverted distance to the nearest of randomly scattered points, which gives round billows.
+    Worley,
+    /// Perlin noise dilated by Worley noise: connected Perlin shapes with billowy cauliflower edges.
+    PerlinWorley,
+}
+
+impl NoiseType {
+    /// Average value of one octave, measured over many random samples.
+    fn mean(self) -> f64 {
+        match self {
+            NoiseType::Value => 0.5,
+            NoiseType::Perlin => 0.5,
+            NoiseType::Worley => 0.48,
+            NoiseType::PerlinWorley => 0.74,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<NoiseType> {
+        match name {
+            "value" => Some(NoiseType::Value),
+            "perlin" => Some(NoiseType::Perlin),
+            "worley" => Some(NoiseType::Worley),
+            "perlin_worley" => Some(NoiseType::PerlinWorley),
+            _ => None,
+        }
+    }
+}
+
+/// How the sample position is displaced before the noise is looked up, as chosen by the `warp` configuration key.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WarpType {
+    None,
+    /// Offsets from three Perlin noises, which stretch and squeeze the clouds.
+    Noise,
+    /// Offsets from the curl of three Perlin noises. The field swirls without bunching up, which sweeps the clouds
+    /// into wisps.
+    Curl,
+}
+
+impl WarpType {
+    pub fn from_name(name: &str) -> Option<WarpType> {
+        match name {
+            "none" => Some(WarpType::None),
+            "noise" => Some(WarpType::Noise),
+            "curl" => Some(WarpType::Curl),
+            _ => None,
+        }
+    }
+}
+
+/// Scales the curl down to offsets about as large as those of the `Noise` warp.
+const CURL_NORMALIZATION: f64 = 0.25;
+
+/// Gradients of the Perlin noise, the midpoints of the edges of a cube.
+const GRADIENTS: [[f64; 3]; 12] = [
+    [1.0, 1.0, 0.0], [-1.0, 1.0, 0.0], [1.0, -1.0, 0.0], [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0], [-1.0, 0.0, 1.0], [1.0, 0.0, -1.0], [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0], [0.0, -1.0, 1.0], [0.0, 1.0, -1.0], [0.0, -1.0, -1.0],
+];
+
+/// A stack of cloud layers, sorted from the lowest to the highest.
+#[derive(Debug, Clone)]
+pub struct CloudLayers {
+    layers: Vec<Cloud>,
+}
+
+impl CloudLayers {
+    pub fn new(mut layers: Vec<Cloud>) -> Self {
+        layers.sort_by(|a, b| a.min_height.total_cmp(&b.min_height));
+
+        CloudLayers { layers }
+    }
+
+    pub fn layers(&self) -> &[Cloud] {
+        &self.layers
+    }
+
+    /// Whether the height lies inside any of the layers.
+    pub fn contains(&self, height: f64) -> bool {
+        self.layers.iter().any(|layer| layer.contains(height))
+    }
+
+    /// The summed density of every layer that contains the position.
+    pub fn get_density(&self, position: Vec3) -> f64 {
+        self.layers.iter().filter(|layer| layer.contains(position.y)).map(|layer| layer.get_density(position)).sum()
+    }
+
+    /// Distance along the direction from a position outside every layer to the edge of the next layer it
+    /// enters, or `None` if it leaves the stack.
+    pub fn distance_to_next_layer(&self, position: Vec3, direction: Vec3) -> Option<f64> {
+        if direction.y > 0.0 {
+            self.layers.iter().find(|layer| layer.min_height > position.y).map(|layer| (layer.min_height - position.y) / direction.y)
+        } else if direction.y < 0.0 {
+            self.layers.iter().rev().find(|layer| layer.max_height < position.y).map(|layer| (layer.max_height - position.y) / direction.y)
+        } else {
+            None
+        }
+    }
+}
+
+/// Everything needed to build a `Cloud` layer.
+#[derive(Debug, Clone)]
+pub struct CloudParameters {
+    pub min_height: f64,
+    pub max_height: f64,
+    pub threshold: f64,
+    /// Width of the noise range above the threshold over which density ramps from 0 to 1.
+    pub softness: f64,
+    /// Number of noise octaves, from 1 to 30.
+    pub levels: u32,
+    pub seed: i32,
+    /// Size of the grid cells of the finest octave.
+    pub scale: f64,
+    pub density: f64,
+    pub profile: HeightProfile,
+    pub noise: NoiseType,
+    /// Weights of the octaves from the coarsest to the finest, normalized to sum to 1. When empty, each octave
+    /// weighs half as much as the one before it.
+    pub octave_weights: Vec<f64>,
+    pub warp: WarpType,
+    pub warp_strength: f64,
+    pub warp_scale: f64,
+}
 
 #[derive(Debug, Clone)]
 pub struct Cloud {
     pub min_height: f64,
     pub max_height: f64,
     threshold: f64,
+    softness: f64,
     levels: u32,
     seed: i32,
-    scale: f64
+    scale: f64,
+    density: f64,
+    profile: HeightProfile,
+    noise: NoiseType,
+    /// Weight of every octave from the coarsest to the finest, summing to 1.
+    weights: Vec<f64>,
+    /// Sum of the weights of the octaves after each one.
+    remaining: Vec<f64>,
+    warp: WarpType,
+    warp_strength: f64,
+    warp_scale: f64,
+    coverage: Option<Coverage>,
+    coverage_strength: f64,
+    /// Height profile where the coverage map's cloud type is 1, blended with `profile` in between.
+    coverage_profile: Option<HeightProfile>,
+    /// How far the wind has carried the layer.
+    drift: Vec3,
+    /// How far the noise has moved through its fourth dimension, in the same units as the heights.
+    evolution: f64,
 }
 
 impl Cloud {
-    pub fn new(min_height: f64, max_height: f64, threshold: f64, levels: u32, seed: i32, scale: f64) -> Self {
-        Cloud { min_height, max_height, threshold, levels, seed, scale }
+    /// Fails for 0 or more than 30 levels, or for octave weights that are not one non-negative weight per level with
+    /// a positive sum.
+    pub fn new(parameters: &CloudParameters) -> Result<Self, SkyboxError> {
+        let CloudParameters { min_height, max_height, threshold, softness, levels, seed, scale, density, ref profile, noise, ref octave_weights, warp, warp_strength, warp_scale } = *parameters;
+
+        if !(1..=30).contains(&levels) {
+            return Err(SkyboxError::InvalidArgument { name: "noise levels", reason: format!("must be between 1 and 30, got {}", levels) });
+        }
+        if !octave_weights.is_empty() {
+            if octave_weights.len() != levels as usize {
+                return Err(SkyboxError::InvalidArgument { name: "octave weights", reason: format!("needs one weight per noise level ({}), got {}", levels, octave_weights.len()) });
+            }
+            if !(octave_weights.iter().all(|&weight| weight >= 0.0) && octave_weights.iter().sum::<f64>() > 0.0) {
+                return Err(SkyboxError::InvalidArgument { name: "octave weights", reason: "must not be negative and must sum to more than 0".to_string() });
+            }
+        }
+
+        let (weights, remaining) = if octave_weights.is_empty() {
+            let divisor = (2_i32.pow(levels) - 1) as f64;
+            let dividends = (0..levels).rev().map(|level| 2_i32.pow(level));
+
+            (dividends.clone().map(|dividend| dividend as f64 / divisor).collect(), dividends.map(|dividend| (dividend - 1) as f64 / divisor).collect())
+        } else {
+            let total: f64 = octave_weights.iter().sum();
+
+            (
+                octave_weights.iter().map(|weight| weight / total).collect(),
+                (0..octave_weights.len()).map(|octave| octave_weights[octave + 1..].iter().sum::<f64>() / total).collect(),
+            )
+        };
+
+        Ok(Cloud { min_height, max_height, threshold, softness, levels, seed, scale, density, profile: profile.clone(), noise, weights, remaining, warp, warp_strength, warp_scale, coverage: None, coverage_strength: 0.0, coverage_profile: None, drift: Vec3::new(0.0, 0.0, 0.0), evolution: 0.0 })
     }
-    
-    pub fn get_density(&self, position: Vec3) -> bool {
-        let x = position.x;
-        let y = position.y;
-        let z = position.z;
 
-        let divisor = (2_i32.pow(self.levels) - 1) as f64;
-        let mut dividend = 2_i32.pow(self.levels - 1);
+    /// Varies the clouds over the xz-plane. Full coverage lowers the threshold by `strength` and no coverage raises
+    /// it by as much. Where the cloud type is above 0, the height profile is blended toward `profile`, if given.
+    pub fn with_coverage(mut self, coverage: Coverage, strength: f64, profile: Option<HeightProfile>) -> Self {
+        self.coverage = Some(coverage);
+        self.coverage_strength = strength;
+        self.coverage_profile = profile;
+
+        self
+    }
+
+    /// Moves the layer to `time`: its clouds drift with the wind, given per unit of time over the xz-plane, and
+    /// change shape at `evolution` units per unit of time. An octave has changed completely once the evolution has
+    /// covered the size of its grid cells. The coverage map stays in place, like weather over the ground.
+    pub fn with_motion(mut self, wind_x: f64, wind_z: f64, evolution: f64, time: f64) -> Self {
+        self.drift = Vec3::new(wind_x, 0.0, wind_z) * time;
+        self.evolution = evolution * time;
+
+        self
+    }
+
+    pub fn contains(&self, height: f64) -> bool {
+        height >= self.min_height && height <= self.max_height
+    }
+
+    /// Cloud density in [0, `density`]. The noise, multiplied by the height profile, is remapped so that values at
+    /// the threshold give 0 and values `softness` above it give full density; a softness of 0 gives the hard-edged
+    /// clouds of a plain threshold test. Octaves are summed coarse to fine, and the sum stops as soon as the remaining
+    /// octaves can no longer lift it above the threshold or can only push it further into the fully dense range.
+    pub fn get_density(&self, position: Vec3) -> f64 {
+        let height = (position.y - self.min_height) / (self.max_height - self.min_height);
+        let mut profile = self.profile.evaluate(height);
+        let mut threshold = self.threshold;
+
+        if let Some(coverage) = &self.coverage {
+            let [coverage_value, cloud_type] = coverage.sample(position.x, position.z);
+
+            threshold += self.coverage_strength * (1.0 - 2.0 * coverage_value);
+            if let Some(coverage_profile) = &self.coverage_profile {
+                profile += (coverage_profile.evaluate(height) - profile) * cloud_type;
+            }
+        }
+
+        if profile <= 0.0 {
+            return 0.0;
+        }
+
+        // Dividing the threshold by the profile is the same as multiplying the noise by it.
+        let threshold = threshold / profile;
+        let softness = self.softness / profile;
+
+        // The clouds drift with the wind through a coverage map that stays fixed to the ground.
+        let warped = self.warp(position - self.drift);
+        let x = warped.x;
+        let y = warped.y;
+        let z = warped.z;
+
+        let mut scale2 = 2_i32.pow(self.levels - 1);
         let mut density = 0.0;
 
-        for _ in 0..(self.levels - 1) {
-            density += self.get_density_at_scale(dividend, x, y, z) * (dividend as f64 / divisor);
-            if density < self.threshold - (dividend - 1) as f64 / divisor {
-                return false;
+        let full_density = threshold + softness;
+
+        for octave in 0..(self.levels - 1) as usize {
+            if self.weights[octave] > 0.0 {
+                density += self.get_evolving_density(scale2, x, y, z) * self.weights[octave];
+            }
+            if density < threshold - self.remaining[octave] {
+                return 0.0;
             }
-            if density >= self.threshold {
-                return true;
+            if density >= full_density {
+                return self.density;
             }
 
-            dividend /= 2;
+            scale2 /= 2;
         }
 
-        density += self.get_density_at_scale(1, x, y, z) * (1.0 / divisor);
-        if density < self.threshold {
-            return false;
+        let finest = (self.levels - 1) as usize;
+        if self.weights[finest] > 0.0 {
+            density += self.get_evolving_density(1, x, y, z) * self.weights[finest];
         }
-        true
+        Cloud::remap(density, threshold, softness) * self.density
     }
 
-    fn get_density_at_scale(&self, scale2: i32, x: f64, y: f64, z: f64) -> f64 {
-        let scale_combined = scale2 as f64 * self.scale;
+    fn remap(density: f64, threshold: f64, softness: f64) -> f64 {
+        if density < threshold {
+            return 0.0;
+        }
+        if density >= threshold + softness {
+            return 1.0;
+        }
 
+        (density - threshold) / softness
+    }
+
+    /// The noise of one octave in [0, 1] as it evolves. Time is cut into slices of independent noise, one per cell
+    /// size of evolution, and the noise is blended from one slice into the next. The blend is scaled so that it
+    /// keeps the contrast of a single slice, as a plain average of two independent noises would thin the clouds
+    /// halfway between slices. `scale2` is the size of its grid cells in units of the finest octave.
+    fn get_evolving_density(&self, scale2: i32, x: f64, y: f64, z: f64) -> f64 {
+        let cell_size = scale2 as f64 * self.scale;
+        if self.evolution == 0.0 {
+            return self.get_density_at_scale(scale2, cell_size, x, y, z);
+        }
+
+        let w = self.evolution / cell_size;
+        let slice = w.floor();
+        let amount = Cloud::extremify(w - slice);
+
+        let mean = self.noise.mean();
+        let current = self.get_density_at_scale(Cloud::slice_salt(scale2, slice as i32), cell_size, x, y, z) - mean;
+        let next = self.get_density_at_scale(Cloud::slice_salt(scale2, slice as i32 + 1), cell_size, x, y, z) - mean;
+        let deviation = (current * (1.0 - amount) + next * amount) / ((1.0 - amount) * (1.0 - amount) + amount * amount).sqrt();
+
+        (mean + deviation).clamp(0.0, 1.0)
+    }
+
+    /// Salt of an octave in an evolution slice. Slice 0 keeps the salt of the static noise.
+    fn slice_salt(scale2: i32, slice: i32) -> i32 {
+        if slice == 0 {
+            return scale2;
+        }
+
+        // Unsigned shifts keep every step invertible, unlike `hash_i32`, which loses the sign bit.
+        let mut hash = (slice as u32).wrapping_mul(0x9e3779b1) ^ scale2 as u32;
+        hash = (hash ^ (hash >> 16)).wrapping_mul(0x45d9f3b);
+        hash = (hash ^ (hash >> 16)).wrapping_mul(0x45d9f3b);
+
+        (hash ^ (hash >> 16)) as i32
+    }
+
+    /// The noise of one octave in [0, 1] on a grid of `cell_size`, with `salt` telling apart independent noises.
+    fn get_density_at_scale(&self, salt: i32, cell_size: f64, x: f64, y: f64, z: f64) -> f64 {
+        match self.noise {
+            NoiseType::Value => self.get_value_noise(salt, cell_size, x, y, z),
+            NoiseType::Perlin => self.get_perlin_noise(salt, cell_size, x, y, z),
+            NoiseType::Worley => self.get_worley_noise(salt, cell_size, x, y, z),
+            NoiseType::PerlinWorley => {
+                let worley = self.get_worley_noise(salt, cell_size, x, y, z);
+
+                worley + self.get_perlin_noise(salt, cell_size, x, y, z) * (1.0 - worley)
+            },
+        }
+    }
+
+    fn get_value_noise(&self, salt: i32, scale_combined: f64, x: f64, y: f64, z: f64) -> f64 {
         let qx = x.div_euclid(scale_combined);
         let qy = y.div_euclid(scale_combined);
         let qz = z.div_euclid(scale_combined);
@@ -65,14 +362,14 @@ impl Cloud {
 
         Cloud::interpolate_3d(
             [
-                self.hash(scale2, x0, y0, z0),
-                self.hash(scale2, x1, y0, z0),
-                self.hash(scale2, x0, y1, z0),
-                self.hash(scale2, x1, y1, z0),
-                self.hash(scale2, x0, y0, z1),
-                self.hash(scale2, x1, y0, z1),
-                self.hash(scale2, x0, y1, z1),
-                self.hash(scale2, x1, y1, z1),
+                self.hash(salt, x0, y0, z0),
+                self.hash(salt, x1, y0, z0),
+                self.hash(salt, x0, y1, z0),
+                self.hash(salt, x1, y1, z0),
+                self.hash(salt, x0, y0, z1),
+                self.hash(salt, x1, y0, z1),
+                self.hash(salt, x0, y1, z1),
+                self.hash(salt, x1, y1, z1),
             ],
             [
                 Cloud::extremify(ax),
@@ -82,18 +379,145 @@ impl Cloud {
         )
     }
 
-    fn hash(&self, scale2: i32, x: i32, y: i32, z: i32) -> f64 {
-        let mut val = Cloud::hash_i32(self.seed);
-        val = Cloud::hash_i32(val.wrapping_add(x));
-        val = Cloud::hash_i32(val.wrapping_add(y));
-        val = Cloud::hash_i32(val.wrapping_add(z));
-        val = Cloud::hash_i32(val.wrapping_add(scale2));
+    fn get_perlin_noise(&self, salt: i32, cell_size: f64, x: f64, y: f64, z: f64) -> f64 {
+        (0.5 + 0.5 * self.perlin(salt, cell_size, x, y, z)).clamp(0.0, 1.0)
+    }
 
-        (val as f64 + 2147483648.0) / 4294967295.0
+    /// Perlin noise in about [-1, 1] on a grid of `cell_size`, with `salt` telling apart independent noises.
+    fn perlin(&self, salt: i32, cell_size: f64, x: f64, y: f64, z: f64) -> f64 {
+        let qx = x.div_euclid(cell_size);
+        let qy = y.div_euclid(cell_size);
+        let qz = z.div_euclid(cell_size);
+
+        let (x0, y0, z0) = (qx as i32, qy as i32, qz as i32);
+        let (ax, ay, az) = (x / cell_size - qx, y / cell_size - qy, z / cell_size - qz);
+
+        // Dot product of the corner's gradient with the offset from that corner.
+        let corner = |dx: i32, dy: i32, dz: i32| {
+            let gradient = GRADIENTS[((self.hash(salt, x0 + dx, y0 + dy, z0 + dz) * 12.0) as usize).min(11)];
+
+            gradient[0] * (ax - dx as f64) + gradient[1] * (ay - dy as f64) + gradient[2] * (az - dz as f64)
+        };
+
+        Cloud::interpolate_3d(
+            [corner(0, 0, 0), corner(1, 0, 0), corner(0, 1, 0), corner(1, 1, 0), corner(0, 0, 1), corner(1, 0, 1), corner(0, 1, 1), corner(1, 1, 1)],
+            [Cloud::extremify(ax), Cloud::extremify(ay), Cloud::extremify(az)],
+        )
+    }
+
+    /// Gradient of `perlin` per grid cell, from the derivatives of the gradients and of the quintic fade.
+    fn perlin_gradient(&self, salt: i32, cell_size: f64, x: f64, y: f64, z: f64) -> Vec3 {
+        let qx = x.div_euclid(cell_size);
+        let qy = y.div_euclid(cell_size);
+        let qz = z.div_euclid(cell_size);
+
+        let (x0, y0, z0) = (qx as i32, qy as i32, qz as i32);
+        let (ax, ay, az) = (x / cell_size - qx, y / cell_size - qy, z / cell_size - qz);
+
+        let gradient = |dx: i32, dy: i32, dz: i32| {
+            let gradient = GRADIENTS[((self.hash(salt, x0 + dx, y0 + dy, z0 + dz) * 12.0) as usize).min(11)];
+
+            Vec3::new(gradient[0], gradient[1], gradient[2])
+        };
+        let g = [gradient(0, 0, 0), gradient(1, 0, 0), gradient(0, 1, 0), gradient(1, 1, 0), gradient(0, 0, 1), gradient(1, 0, 1), gradient(0, 1, 1), gradient(1, 1, 1)];
+
+        // Corner values in the same order as the gradients.
+        let c: [f64; 8] = std::array::from_fn(|i| g[i].dot(Vec3::new(ax - (i & 1) as f64, ay - ((i >> 1) & 1) as f64, az - (i >> 2) as f64)));
+
+        let (u, v, w) = (Cloud::extremify(ax), Cloud::extremify(ay), Cloud::extremify(az));
+        let fade_derivative = |n: f64| 30.0 * n * n * (n - 1.0) * (n - 1.0);
+        let (du, dv, dw) = (fade_derivative(ax), fade_derivative(ay), fade_derivative(az));
+
+        // The noise written as k0 + k1 u + k2 v + k3 w + k4 uv + k5 vw + k6 wu + k7 uvw.
+        let k1 = c[1] - c[0];
+        let k2 = c[2] - c[0];
+        let k3 = c[4] - c[0];
+        let k4 = c[0] - c[1] - c[2] + c[3];
+        let k5 = c[0] - c[2] - c[4] + c[6];
+        let k6 = c[0] - c[1] - c[4] + c[5];
+        let k7 = -c[0] + c[1] + c[2] - c[3] + c[4] - c[5] - c[6] + c[7];
+
+        let interpolate = |a: Vec3, b: Vec3, amount: f64| a + (b - a) * amount;
+        let gradients = interpolate(
+            interpolate(interpolate(g[0], g[1], u), interpolate(g[2], g[3], u), v),
+            interpolate(interpolate(g[4], g[5], u), interpolate(g[6], g[7], u), v),
+            w,
+        );
+
+        gradients + Vec3::new(
+            du * (k1 + k4 * v + k6 * w + k7 * v * w),
+            dv * (k2 + k5 * w + k4 * u + k7 * w * u),
+            dw * (k3 + k6 * u + k5 * v + k7 * u * v),
+        )
+    }
+
+    /// Displaces a position by the warp field before the noise is looked up.
+    fn warp(&self, position: Vec3) -> Vec3 {
+        // Negative salts keep the warp noises apart from the octaves, which use positive ones.
+        let (x, y, z) = (position.x, position.y, position.z);
+
+        let offset = match self.warp {
+            WarpType::None => return position,
+            WarpType::Noise => Vec3::new(
+                self.perlin(-1, self.warp_scale, x, y, z),
+                self.perlin(-2, self.warp_scale, x, y, z),
+                self.perlin(-3, self.warp_scale, x, y, z),
+            ),
+            WarpType::Curl => {
+                let a = self.perlin_gradient(-1, self.warp_scale, x, y, z);
+                let b = self.perlin_gradient(-2, self.warp_scale, x, y, z);
+                let c = self.perlin_gradient(-3, self.warp_scale, x, y, z);
+
+                Vec3::new(c.y - b.z, a.z - c.x, b.x - a.y) * CURL_NORMALIZATION
+            },
+        };
+
+        position + offset * self.warp_strength
+    }
+
+    fn get_worley_noise(&self, salt: i32, scale_combined: f64, x: f64, y: f64, z: f64) -> f64 {
+        let qx = x.div_euclid(scale_combined);
+        let qy = y.div_euclid(scale_combined);
+        let qz = z.div_euclid(scale_combined);
+
+        let (x0, y0, z0) = (qx as i32, qy as i32, qz as i32);
+        let (ax, ay, az) = (x / scale_combined - qx, y / scale_combined - qy, z / scale_combined - qz);
+
+        // Every cell holds one point; the nearest is in this cell or one of its neighbours.
+        let mut min_dist_sq = f64::MAX;
+        for dz in -1..=1 {
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let val = self.hash_cell(salt, x0 + dx, y0 + dy, z0 + dz);
+                    let val_y = hash_i32(val);
+                    let val_z = hash_i32(val_y);
+
+                    let px = dx as f64 + Cloud::to_unit(val) - ax;
+                    let py = dy as f64 + Cloud::to_unit(val_y) - ay;
+                    let pz = dz as f64 + Cloud::to_unit(val_z) - az;
+
+                    min_dist_sq = min_dist_sq.min(px * px + py * py + pz * pz);
+                }
+            }
+        }
+
+        (1.0 - min_dist_sq.sqrt()).max(0.0)
+    }
+
+    fn hash(&self, salt: i32, x: i32, y: i32, z: i32) -> f64 {
+        Cloud::to_unit(self.hash_cell(salt, x, y, z))
     }
 
-    fn hash_i32(x: i32) -> i32 {
-        ((x >> 16) ^ x).wrapping_mul(0x45d9f3b)
+    fn hash_cell(&self, salt: i32, x: i32, y: i32, z: i32) -> i32 {
+        let mut val = hash_i32(self.seed);
+        val = hash_i32(val.wrapping_add(x));
+        val = hash_i32(val.wrapping_add(y));
+        val = hash_i32(val.wrapping_add(z));
+        hash_i32(val.wrapping_add(salt))
+    }
+
+    fn to_unit(val: i32) -> f64 {
+        (val as f64 + 2147483648.0) / 4294967295.0
     }
 
     fn interpolate_3d(v: [f64; 8], p: [f64; 3]) -> f64 {
@@ -120,4 +544,92 @@ impl Cloud {
         //n * n * (3.0 - 2.0 * n)
         n * n * n * (n * (n * 6.0 - 15.0) + 10.0)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fast_rng::Frng;
+
+    fn parameters(noise: NoiseType) -> CloudParameters {
+        CloudParameters {
+            min_height: 0.0,
+            max_height: 100.0,
+            threshold: 0.5,
+            softness: 0.0,
+            levels: 4,
+            seed: 1234,
+            scale: 1.0,
+            density: 1.0,
+            profile: HeightProfile::Uniform,
+            noise,
+            octave_weights: Vec::new(),
+            warp: WarpType::None,
+            warp_strength: 0.0,
+            warp_scale: 1.0,
+        }
+    }
+
+    fn cloud(noise: NoiseType) -> Cloud {
+        Cloud::new(&parameters(noise)).unwrap()
+    }
+
+    /// Mean and standard deviation of the finest octave.
+    fn statistics(cloud: &Cloud) -> (f64, f64) {
+        let mut frng = Frng::new(7);
+        let count = 100_000;
+        let samples: Vec<f64> = (0..count).map(|_| cloud.get_evolving_density(1, frng.next_double(0.0, 500.0), frng.next_double(0.0, 500.0), frng.next_double(0.0, 500.0))).collect();
+
+        let mean = samples.iter().sum::<f64>() / count as f64;
+        let variance = samples.iter().map(|sample| (sample - mean) * (sample - mean)).sum::<f64>() / count as f64;
+
+        (mean, variance.sqrt())
+    }
+
+    #[test]
+    fn evolution_keeps_the_noise_statistics() {
+        for noise in [NoiseType::Value, NoiseType::Perlin, NoiseType::Worley, NoiseType::PerlinWorley] {
+            let (mean, deviation) = statistics(&cloud(noise));
+            assert!((mean - noise.mean()).abs() < 0.01, "{:?} mean {}", noise, mean);
+
+            // Halfway between two slices, where a plain blend would lose the most contrast.
+            let (evolved_mean, evolved_deviation) = statistics(&cloud(noise).with_motion(0.0, 0.0, 0.5, 1.0));
+            assert!((evolved_mean - mean).abs() < 0.01, "{:?} mean {} instead of {}", noise, evolved_mean, mean);
+            assert!((evolved_deviation / deviation - 1.0).abs() < 0.05, "{:?} deviation {} instead of {}", noise, evolved_deviation, deviation);
+        }
+    }
+
+    #[test]
+    fn slice_salts_are_distinct() {
+        // Also distinct from the salts of the warp noises.
+        let mut salts: Vec<i32> = (0..31).flat_map(|level| (-64..64).map(move |slice| Cloud::slice_salt(1 << level, slice))).chain([-1, -2, -3]).collect();
+        let count = salts.len();
+        salts.sort_unstable();
+        salts.dedup();
+
+        assert_eq!(salts.len(), count);
+        assert_eq!(Cloud::slice_salt(256, 0), 256);
+    }
+
+    #[test]
+    fn wind_moves_the_clouds() {
+        let still = cloud(NoiseType::Value);
+        let moving = cloud(NoiseType::Value).with_motion(3.0, -2.0, 0.0, 10.0);
+
+        for position in [Vec3::new(1.0, 20.0, 2.0), Vec3::new(-40.5, 60.0, 13.25)] {
+            assert_eq!(moving.get_density(position + Vec3::new(30.0, 0.0, -20.0)), still.get_density(position));
+        }
+    }
+
+    #[test]
+    fn new_rejects_unusable_octaves() {
+        let new = |levels: u32, octave_weights: &[f64]| Cloud::new(&CloudParameters { levels, octave_weights: octave_weights.to_vec(), ..parameters(NoiseType::Value) });
+
+        assert!(new(1, &[]).is_ok());
+        assert!(new(30, &[]).is_ok());
+        assert!(new(3, &[0.0, 1.0, 0.5]).is_ok());
+        for (levels, octave_weights) in [(0, &[][..]), (31, &[]), (3, &[1.0, 1.0]), (2, &[1.0, -0.5]), (2, &[0.0, 0.0])] {
+            assert!(matches!(new(levels, octave_weights), Err(SkyboxError::InvalidArgument { .. })), "{} levels, {:?}", levels, octave_weights);
+        }
+    }
+}
@@ -1,6 +1,8 @@
 // Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
 
+use std::fmt;
 use std::ops::{Add, Div, Mul};
+use std::str::FromStr;
 
 #[derive(Debug, Copy, Clone)]
 pub struct Color {
@@ -14,13 +16,9 @@ impl Color {
         Color {r, g, b}
     }
 
-    pub fn from_str(v: &str) -> Color {
-        assert_eq!(v.len(), 6);
-        let input = [
-            u8::from_str_radix(&v[0..2], 16).unwrap(),
-            u8::from_str_radix(&v[2..4], 16).unwrap(),
-            u8::from_str_radix(&v[4..6], 16).unwrap(),
-        ];
+    /// Converts an 8-bit sRGB color to linear RGB.
+    pub fn from_srgb_8(r: u8, g: u8, b: u8) -> Color {
+        let input = [r, g, b];
 
         let mut output = [0.0; 3];
         for (c_lin, c_s_rgb_8) in output.iter_mut().zip(input.iter()) {
@@ -40,6 +38,20 @@ impl Color {
         }
     }
 
+    pub fn to_array(self) -> [f64; 3] {
+        [self.r, self.g, self.b]
+    }
+
+    /// Relative luminance of the linear sRGB color.
+    pub fn luminance(self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    /// Applies `f` to every channel.
+    pub fn map(self, f: impl Fn(f64) -> f64) -> Color {
+        Color::new(f(self.r), f(self.g), f(self.b))
+    }
+
     pub fn mix(self, c: Color, amount: f64) -> Color {
         Color {
             r: (1.0-amount) * self.r + amount*c.r,
@@ -81,6 +93,54 @@ impl Color {
 
         output
     }
+
+    /// Shared-exponent encoding used by Radiance images. Negative components are clamped to zero.
+    pub fn to_rgbe(self) -> [u8; 4] {
+        let [r, g, b] = self.to_array().map(|c| c.max(0.0));
+        let v = r.max(g).max(b);
+
+        if v < 1e-32 {
+            return [0, 0, 0, 0];
+        }
+
+        // v = m * 2^e with m in [0.5, 1)
+        let mut e = v.log2().floor() as i32 + 1;
+        if v / 2.0f64.powi(e) >= 1.0 {
+            e += 1;
+        } else if v / 2.0f64.powi(e) < 0.5 {
+            e -= 1;
+        }
+        let scale = 256.0 / 2.0f64.powi(e);
+
+        [(r * scale) as u8, (g * scale) as u8, (b * scale) as u8, (e + 128) as u8]
+    }
+}
+
+/// Returned when a string is not a 6-digit hex color.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseColorError;
+
+impl fmt::Display for ParseColorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected a 6-digit hex color")
+    }
+}
+
+impl std::error::Error for ParseColorError {}
+
+impl FromStr for Color {
+    type Err = ParseColorError;
+
+    /// Parses an sRGB hex color such as `ffe1a0`.
+    fn from_str(v: &str) -> Result<Color, ParseColorError> {
+        if v.len() != 6 || !v.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ParseColorError);
+        }
+
+        let channel = |i: usize| u8::from_str_radix(&v[i..i + 2], 16).map_err(|_| ParseColorError);
+
+        Ok(Color::from_srgb_8(channel(0)?, channel(2)?, channel(4)?))
+    }
 }
 
 impl Add<Color> for Color {
@@ -109,6 +169,19 @@ impl Div<f64> for Color {
     }
 }
 
+impl Mul<Color> for Color {
+    type Output = Color;
+
+    #[inline]
+    fn mul(self, other: Color) -> Color {
+        Color {
+            r: self.r * other.r,
+            g: self.g * other.g,
+            b: self.b * other.b,
+        }
+    }
+}
+
 impl Mul<f64> for Color {
     type Output = Color;
 
@@ -0,0 +1,1004 @@
+// Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
+
+use std::f64::consts::FRAC_PI_4;
+use std::fs;
+use std::path::{Path, PathBuf};
+use configparser::ini::Ini;
+use crate::color::Color;
+use crate::error::{ConfigIssue, SkyboxError};
+use crate::view::Projection;
+use crate::layout::{CubemapConvention, CubemapLayout};
+use crate::image::{ExrCompression, ExrPrecision};
+use crate::output::ImageFormat;
+use crate::phase::PhaseFunction;
+use crate::height_profile::HeightProfile;
+use crate::cloud::{NoiseType, WarpType};
+use crate::coverage::CoverageMode;
+use crate::background::SkyModel;
+use crate::atmosphere::AtmosphereParameters;
+use crate::sun::{DateTime, SunLocation, SunMode};
+
+/// All parameters needed to render a skybox. Mirrors the sections of *config.ini*.
+#[derive(Debug, Clone)]
+pub struct SkyboxConfig {
+    pub images: ImagesConfig,
+    pub slices: SlicesConfig,
+    pub clouds: CloudsConfig,
+    pub background: BackgroundConfig,
+    pub sun: SunConfig,
+    pub night: NightConfig,
+    pub quality: QualityConfig,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImagesConfig {
+    pub image_width: usize,
+    pub image_height: usize,
+    pub render_north_only: bool,
+    pub raw_color: bool,
+    pub projection: Projection,
+    pub layout: CubemapLayout,
+    pub convention: CubemapConvention,
+    pub format: ImageFormat,
+    pub exr_precision: ExrPrecision,
+    pub exr_compression: ExrCompression,
+}
+
+#[derive(Debug, Clone)]
+pub struct SlicesConfig {
+    pub use_multithreading: bool,
+    /// Edge length of the square tiles handed out to the worker threads.
+    pub tile_size: usize,
+    /// Number of worker threads, 0 to use every available core.
+    pub thread_count: usize,
+}
+
+/// The `[clouds]` section. Its layer keys describe the first cloud layer, and every `[clouds.<name>]` section adds
+/// another one.
+#[derive(Debug, Clone)]
+pub struct CloudsConfig {
+    pub layers: Vec<CloudLayerConfig>,
+    /// Moment of an animation. The layers drift with their wind and evolve as it advances.
+    pub time: f64,
+    /// Extinction coefficient at full density, per unit of distance.
+    pub extinction: f64,
+    pub min_fog_dist: f64,
+    pub max_fog_dist: f64,
+    pub step_size: f64,
+    pub step_count: usize,
+    /// Sample the sun directly at every scattering point instead of relying on random paths to hit it.
+    pub direct_sun: bool,
+    pub light_step_count: usize,
+    pub light_step_size: f64,
+    pub phase_function: PhaseFunction,
+}
+
+/// One slab of clouds. Keys missing from a `[clouds.<name>]` section are taken from `[clouds]`.
+#[derive(Debug, Clone)]
+pub struct CloudLayerConfig {
+    /// The section the layer comes from, used in validation messages.
+    pub section: String,
+    pub min_height: f64,
+    pub max_height: f64,
+    pub cloud_threshold: f64,
+    /// Width of the noise range above the threshold over which density ramps from 0 to 1.
+    pub cloud_softness: f64,
+    pub noise_levels: u32,
+    pub noise_scale: f64,
+    pub cloud_seed: u64,
+    /// Multiplies the layer density, so that e.g. cirrus can be made thinner than cumulus.
+    pub density: f64,
+    pub height_profile: HeightProfile,
+    pub noise_type: NoiseType,
+    /// Weights of the `noise_levels` octaves from the coarsest to the finest. Empty to halve the weight every octave.
+    pub octave_weights: Vec<f64>,
+    pub warp: WarpType,
+    /// How far the warp moves sample positions, in the same units as the heights.
+    pub warp_strength: f64,
+    /// Size of the features of the warp field. Larger values warp at a lower frequency.
+    pub warp_scale: f64,
+    pub coverage: CoverageMode,
+    /// A PNG image with the coverage in red (or gray) and the cloud type in blue, north up.
+    pub coverage_image: Option<PathBuf>,
+    /// Width and depth of the area the coverage map covers.
+    pub coverage_scale: f64,
+    pub coverage_offset_x: f64,
+    pub coverage_offset_z: f64,
+    pub coverage_tiling: bool,
+    /// How far full or no coverage moves `cloud_threshold`.
+    pub coverage_strength: f64,
+    /// Height profile where the cloud type is 1.
+    pub coverage_profile: Option<HeightProfile>,
+    /// Wind velocity per unit of time toward east (+X) and north (+Z).
+    pub wind_x: f64,
+    pub wind_z: f64,
+    /// How fast the noise morphs, in the same units as the heights per unit of time.
+    pub evolution: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct BackgroundConfig {
+    pub sun_brightness: f64,
+    pub sun_size: f64,
+    pub sun_angle_phi: f64,
+    pub sun_angle_theta: f64,
+    pub sun_color: Color,
+    pub sky_colors: Vec<Color>,
+    pub ground_color: Color,
+    pub sky_model: SkyModel,
+    /// Brightness of the atmosphere sky, in the same units as `sun_brightness`.
+    pub sky_intensity: f64,
+    /// Also the turbidity of the Preetham and Hosek-Wilkie models.
+    pub atmosphere: AtmosphereParameters,
+    pub ground_albedo: f64,
+    /// The *ArHosekSkyModelData_RGB.h* file from the Hosek-Wilkie reference implementation.
+    pub hosek_wilkie_data: Option<PathBuf>,
+}
+
+/// The optional `[sun]` section. With `mode = location` the sun is placed from `location` instead of the
+/// `[background]` angles.
+#[derive(Debug, Clone)]
+pub struct SunConfig {
+    pub mode: SunMode,
+    pub location: SunLocation,
+}
+
+/// The optional `[night]` section. The moon and stars are placed for the `[sun]` location, date and time.
+#[derive(Debug, Clone)]
+pub struct NightConfig {
+    pub enabled: bool,
+    pub sky_color: Color,
+    pub moon_size: f64,
+    pub moon_color: Color,
+    pub moon_brightness: f64,
+    /// Number of procedural stars, used when there is no `star_catalog`.
+    pub star_count: usize,
+    pub star_seed: u64,
+    pub star_size: f64,
+    pub star_brightness: f64,
+    /// A CSV file with one `right_ascension, declination, magnitude[, b-v]` line per star.
+    pub star_catalog: Option<PathBuf>,
+    pub milky_way: bool,
+    pub milky_way_brightness: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct QualityConfig {
+    pub renderer_seed: u64,
+    pub pixel_width: usize,
+    pub bundle_size: usize,
+}
+
+impl SkyboxConfig {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, SkyboxError> {
+        SkyboxConfig::from_ini(&read_ini(path)?)
+    }
+
+    pub fn from_ini_str(config_str: &str) -> Result<Self, SkyboxError> {
+        SkyboxConfig::from_ini(&parse_ini(config_str)?)
+    }
+
+    pub fn from_ini(config: &Ini) -> Result<Self, SkyboxError> {
+        let images = ImagesConfig {
+            image_width: get_uint(config, "images", "image_width")?,
+            image_height: get_uint(config, "images", "image_height")?,
+            render_north_only: get_bool(config, "images", "render_north_only")?,
+            raw_color: get_bool(config, "images", "raw_color")?,
+            projection: get_choice(config, "images", "projection", Projection::Cubemap, Projection::from_name, "cubemap or equirectangular")?,
+            layout: get_choice(config, "images", "layout", CubemapLayout::Separate, CubemapLayout::from_name, "separate, horizontal_cross, vertical_cross, strip or grid")?,
+            convention: get_choice(config, "images", "convention", CubemapConvention::DirectX, CubemapConvention::from_name, "opengl, directx or unity")?,
+            format: get_choice(config, "images", "format", ImageFormat::Png, ImageFormat::from_name, "png, exr or hdr")?,
+            exr_precision: get_choice(config, "images", "exr_precision", ExrPrecision::Half, ExrPrecision::from_name, "half or float")?,
+            exr_compression: get_choice(config, "images", "exr_compression", ExrCompression::Zip, ExrCompression::from_name, "none, rle, zips, zip or piz")?,
+        };
+
+        let slices = SlicesConfig {
+            use_multithreading: get_bool(config, "slices", "use_multithreading")?,
+            tile_size: get_uint_or(config, "slices", "tile_size", 16)?,
+            thread_count: get_uint_or(config, "slices", "thread_count", 0)?,
+        };
+
+        let first_layer = CloudLayerConfig {
+            section: "clouds".to_string(),
+            min_height: get_float(config, "clouds", "min_height")?,
+            max_height: get_float(config, "clouds", "max_height")?,
+            cloud_threshold: get_float(config, "clouds", "cloud_threshold")?,
+            cloud_softness: get_float_or(config, "clouds", "cloud_softness", 0.0)?,
+            noise_levels: get_uint(config, "clouds", "noise_levels")?,
+            noise_scale: get_float(config, "clouds", "noise_scale")?,
+            cloud_seed: get_uint(config, "clouds", "cloud_seed")?,
+            density: get_float_or(config, "clouds", "density", 1.0)?,
+            height_profile: get_height_profile(config, "clouds", "height_profile", HeightProfile::Uniform)?,
+            noise_type: get_choice(config, "clouds", "noise_type", NoiseType::Value, NoiseType::from_name, "value, perlin, worley or perlin_worley")?,
+            octave_weights: get_floats_or(config, "clouds", "octave_weights", Vec::new())?,
+            warp: get_choice(config, "clouds", "warp", WarpType::None, WarpType::from_name, "none, noise or curl")?,
+            warp_strength: get_float_or(config, "clouds", "warp_strength", 16.0)?,
+            warp_scale: get_float_or(config, "clouds", "warp_scale", 64.0)?,
+            coverage: get_choice(config, "clouds", "coverage", CoverageMode::None, CoverageMode::from_name, "none, image or procedural")?,
+            coverage_image: get_path_or(config, "clouds", "coverage_image", None),
+            coverage_scale: get_float_or(config, "clouds", "coverage_scale", 4096.0)?,
+            coverage_offset_x: get_float_or(config, "clouds", "coverage_offset_x", 0.0)?,
+            coverage_offset_z: get_float_or(config, "clouds", "coverage_offset_z", 0.0)?,
+            coverage_tiling: get_bool_or(config, "clouds", "coverage_tiling", true)?,
+            coverage_strength: get_float_or(config, "clouds", "coverage_strength", 0.3)?,
+            coverage_profile: get_optional_height_profile(config, "clouds", "coverage_profile", None)?,
+            wind_x: get_float_or(config, "clouds", "wind_x", 0.0)?,
+            wind_z: get_float_or(config, "clouds", "wind_z", 0.0)?,
+            evolution: get_float_or(config, "clouds", "evolution", 0.0)?,
+        };
+
+        // The INI parser does not keep the section order, so extra layers are read in name order.
+        let mut layer_sections: Vec<String> = config.sections().into_iter().filter(|section| section.starts_with("clouds.")).collect();
+        layer_sections.sort();
+
+        let mut layers = vec![first_layer.clone()];
+        for section in layer_sections {
+            let section = section.as_str();
+            layers.push(CloudLayerConfig {
+                section: section.to_string(),
+                min_height: get_float(config, section, "min_height")?,
+                max_height: get_float(config, section, "max_height")?,
+                cloud_threshold: get_float_or(config, section, "cloud_threshold", first_layer.cloud_threshold)?,
+                cloud_softness: get_float_or(config, section, "cloud_softness", first_layer.cloud_softness)?,
+                noise_levels: get_uint_or(config, section, "noise_levels", first_layer.noise_levels)?,
+                noise_scale: get_float_or(config, section, "noise_scale", first_layer.noise_scale)?,
+                cloud_seed: get_uint_or(config, section, "cloud_seed", first_layer.cloud_seed)?,
+                density: get_float_or(config, section, "density", first_layer.density)?,
+                height_profile: get_height_profile(config, section, "height_profile", first_layer.height_profile.clone())?,
+                noise_type: get_choice(config, section, "noise_type", first_layer.noise_type, NoiseType::from_name, "value, perlin, worley or perlin_worley")?,
+                octave_weights: get_floats_or(config, section, "octave_weights", first_layer.octave_weights.clone())?,
+                warp: get_choice(config, section, "warp", first_layer.warp, WarpType::from_name, "none, noise or curl")?,
+                warp_strength: get_float_or(config, section, "warp_strength", first_layer.warp_strength)?,
+                warp_scale: get_float_or(config, section, "warp_scale", first_layer.warp_scale)?,
+                coverage: get_choice(config, section, "coverage", first_layer.coverage, CoverageMode::from_name, "none, image or procedural")?,
+                coverage_image: get_path_or(config, section, "coverage_image", first_layer.coverage_image.clone()),
+                coverage_scale: get_float_or(config, section, "coverage_scale", first_layer.coverage_scale)?,
+                coverage_offset_x: get_float_or(config, section, "coverage_offset_x", first_layer.coverage_offset_x)?,
+                coverage_offset_z: get_float_or(config, section, "coverage_offset_z", first_layer.coverage_offset_z)?,
+                coverage_tiling: get_bool_or(config, section, "coverage_tiling", first_layer.coverage_tiling)?,
+                coverage_strength: get_float_or(config, section, "coverage_strength", first_layer.coverage_strength)?,
+                coverage_profile: get_optional_height_profile(config, section, "coverage_profile", first_layer.coverage_profile.clone())?,
+                wind_x: get_float_or(config, section, "wind_x", first_layer.wind_x)?,
+                wind_z: get_float_or(config, section, "wind_z", first_layer.wind_z)?,
+                evolution: get_float_or(config, section, "evolution", first_layer.evolution)?,
+            });
+        }
+
+        let clouds = CloudsConfig {
+            layers,
+            time: get_float_or(config, "clouds", "time", 0.0)?,
+            extinction: get_float_or(config, "clouds", "extinction", 1.0)?,
+            min_fog_dist: get_float(config, "clouds", "min_fog_dist")?,
+            max_fog_dist: get_float(config, "clouds", "max_fog_dist")?,
+            step_size: get_float(config, "clouds", "step_size")?,
+            step_count: get_uint(config, "clouds", "step_count")?,
+            direct_sun: get_bool_or(config, "clouds", "direct_sun", true)?,
+            light_step_count: get_uint_or(config, "clouds", "light_step_count", 8)?,
+            light_step_size: get_float_or(config, "clouds", "light_step_size", 8.0)?,
+            phase_function: get_choice(config, "clouds", "phase_function", PhaseFunction::HenyeyGreenstein { g: 0.6 }, PhaseFunction::from_name, "legacy, isotropic, henyey_greenstein, dual_lobe or cornette_shanks")?
+                .with_parameters(
+                    get_float_or(config, "clouds", "phase_g", 0.6)?,
+                    get_float_or(config, "clouds", "phase_backward_g", -0.3)?,
+                    get_float_or(config, "clouds", "phase_forward_weight", 0.8)?,
+                ),
+        };
+
+        let background = BackgroundConfig {
+            sun_brightness: get_float(config, "background", "sun_brightness")?,
+            sun_size: get_float(config, "background", "sun_size")?,
+            sun_angle_phi: get_float(config, "background", "sun_angle_phi")?,
+            sun_angle_theta: get_float(config, "background", "sun_angle_theta")?,
+            sun_color: get_color(config, "background", "sun_color")?,
+            sky_colors: get_colors(config, "background", "sky_colors")?,
+            ground_color: get_color(config, "background", "ground_color")?,
+            sky_model: get_choice(config, "background", "sky_model", SkyModel::Gradient, SkyModel::from_name, "gradient, atmosphere, preetham or hosek_wilkie")?,
+            sky_intensity: get_float_or(config, "background", "sky_intensity", 20.0)?,
+            atmosphere: AtmosphereParameters {
+                planet_radius: get_float_or(config, "background", "planet_radius", 6360.0)?,
+                atmosphere_height: get_float_or(config, "background", "atmosphere_height", 100.0)?,
+                rayleigh_scale_height: get_float_or(config, "background", "rayleigh_scale_height", 8.0)?,
+                mie_scale_height: get_float_or(config, "background", "mie_scale_height", 1.2)?,
+                turbidity: get_float_or(config, "background", "turbidity", 2.0)?,
+                mie_g: get_float_or(config, "background", "mie_g", 0.8)?,
+                ozone: get_bool_or(config, "background", "ozone", true)?,
+                multiple_scattering: get_bool_or(config, "background", "multiple_scattering", true)?,
+            },
+            ground_albedo: get_float_or(config, "background", "ground_albedo", 0.3)?,
+            hosek_wilkie_data: config.get("background", "hosek_wilkie_data").filter(|path| !path.is_empty()).map(PathBuf::from),
+        };
+
+        // The place and time are only needed to compute the sun, so they have no defaults then.
+        let mode = get_choice(config, "sun", "mode", SunMode::Angles, SunMode::from_name, "angles or location")?;
+        let location = match mode {
+            SunMode::Angles => SkyboxConfig::default().sun.location,
+            SunMode::Location => {
+                let date = get_str(config, "sun", "date")?;
+                let time = get_str(config, "sun", "time")?;
+
+                SunLocation {
+                    latitude: get_float(config, "sun", "latitude")?,
+                    longitude: get_float(config, "sun", "longitude")?,
+                    date_time: DateTime::parse_date(&date)
+                        .ok_or_else(|| wrong_type("sun", "date", &date, "a date (YYYY-MM-DD)"))?
+                        .with_time(&time)
+                        .ok_or_else(|| wrong_type("sun", "time", &time, "a time (HH:MM or HH:MM:SS)"))?
+                        .with_utc_offset(get_float_or(config, "sun", "utc_offset", 0.0)?),
+                }
+            },
+        };
+        let sun = SunConfig { mode, location };
+
+        let night = NightConfig {
+            enabled: get_bool_or(config, "night", "enabled", false)?,
+            sky_color: get_color_or(config, "night", "sky_color", Color::from_srgb_8(0x04, 0x08, 0x14))?,
+            moon_size: get_float_or(config, "night", "moon_size", 0.08)?,
+            moon_color: get_color_or(config, "night", "moon_color", Color::from_srgb_8(0xf4, 0xf1, 0xe8))?,
+            moon_brightness: get_float_or(config, "night", "moon_brightness", 2.0)?,
+            star_count: get_uint_or(config, "night", "star_count", 4000)?,
+            star_seed: get_uint_or(config, "night", "star_seed", 0)?,
+            star_size: get_float_or(config, "night", "star_size", 0.003)?,
+            star_brightness: get_float_or(config, "night", "star_brightness", 4.0)?,
+            star_catalog: config.get("night", "star_catalog").filter(|path| !path.is_empty()).map(PathBuf::from),
+            milky_way: get_bool_or(config, "night", "milky_way", true)?,
+            milky_way_brightness: get_float_or(config, "night", "milky_way_brightness", 0.02)?,
+        };
+
+        let quality = QualityConfig {
+            renderer_seed: get_uint(config, "quality", "renderer_seed")?,
+            pixel_width: get_uint(config, "quality", "pixel_width")?,
+            bundle_size: get_uint(config, "quality", "bundle_size")?,
+        };
+
+        Ok(SkyboxConfig { images, slices, clouds, background, sun, night, quality })
+    }
+
+    /// Checks the combinations of values that would otherwise panic or silently produce garbage during rendering.
+    /// Every problem is collected so they can all be fixed in one go.
+    pub fn validate(&self) -> Result<(), SkyboxError> {
+        let mut issues = Vec::new();
+        let mut check = |ok: bool, section: &str, key: &'static str, message: String| {
+            if !ok {
+                issues.push(ConfigIssue { section: section.to_string(), key, message });
+            }
+        };
+
+        let images = &self.images;
+        check(images.image_width > 0, "images", "image_width", "must be at least 1".to_string());
+        check(images.image_height > 0, "images", "image_height", "must be at least 1".to_string());
+        check(
+            images.projection != Projection::Equirectangular || images.image_width == 2 * images.image_height,
+            "images", "image_width",
+            format!("an equirectangular panorama must be twice as wide as it is high, got {}x{}", images.image_width, images.image_height),
+        );
+        if images.layout != CubemapLayout::Separate {
+            check(images.projection == Projection::Cubemap, "images", "layout", "a cube map layout needs the cubemap projection".to_string());
+            check(!images.render_north_only, "images", "layout", "a cube map layout needs all six faces, render_north_only must be false".to_string());
+            check(
+                images.image_width == images.image_height,
+                "images", "layout",
+                format!("a cube map layout needs square faces, got {}x{}", images.image_width, images.image_height),
+            );
+        }
+
+        let slices = &self.slices;
+        check(slices.tile_size > 0, "slices", "tile_size", "must be at least 1".to_string());
+
+        let clouds = &self.clouds;
+        for (index, layer) in clouds.layers.iter().enumerate() {
+            let section = layer.section.as_str();
+            check(
+                layer.min_height < layer.max_height,
+                section, "min_height",
+                format!("must be below max_height ({} >= {})", layer.min_height, layer.max_height),
+            );
+            check((1..=30).contains(&layer.noise_levels), section, "noise_levels", format!("must be between 1 and 30, got {}", layer.noise_levels));
+            check(layer.cloud_softness >= 0.0, section, "cloud_softness", format!("must not be negative, got {}", layer.cloud_softness));
+            check(layer.noise_scale > 0.0, section, "noise_scale", format!("must be positive, got {}", layer.noise_scale));
+            check(layer.density >= 0.0, section, "density", format!("must not be negative, got {}", layer.density));
+            if layer.warp != WarpType::None {
+                check(layer.warp_strength >= 0.0, section, "warp_strength", format!("must not be negative, got {}", layer.warp_strength));
+                check(layer.warp_scale > 0.0, section, "warp_scale", format!("must be positive, got {}", layer.warp_scale));
+            }
+            if layer.coverage != CoverageMode::None {
+                check(layer.coverage_scale > 0.0, section, "coverage_scale", format!("must be positive, got {}", layer.coverage_scale));
+                check(layer.coverage_strength >= 0.0, section, "coverage_strength", format!("must not be negative, got {}", layer.coverage_strength));
+            }
+            if layer.coverage == CoverageMode::Image {
+                check(layer.coverage_image.is_some(), section, "coverage_image", "coverage = image needs the path of a PNG image".to_string());
+            }
+            check(layer.evolution >= 0.0, section, "evolution", format!("must not be negative, got {}", layer.evolution));
+            if !layer.octave_weights.is_empty() {
+                check(
+                    layer.octave_weights.len() == layer.noise_levels as usize,
+                    section, "octave_weights",
+                    format!("needs one weight per noise level ({}), got {}", layer.noise_levels, layer.octave_weights.len()),
+                );
+                check(
+                    layer.octave_weights.iter().all(|&weight| weight >= 0.0) && layer.octave_weights.iter().sum::<f64>() > 0.0,
+                    section, "octave_weights",
+                    "must not be negative and must not all be 0".to_string(),
+                );
+            }
+
+            for other in &clouds.layers[..index] {
+                check(
+                    layer.max_height <= other.min_height || layer.min_height >= other.max_height,
+                    section, "min_height",
+                    format!("the layer overlaps [{}] ({} to {})", other.section, other.min_height, other.max_height),
+                );
+            }
+        }
+        check(clouds.extinction > 0.0, "clouds", "extinction", format!("must be positive, got {}", clouds.extinction));
+        check(
+            clouds.min_fog_dist <= clouds.max_fog_dist,
+            "clouds", "min_fog_dist",
+            format!("must not exceed max_fog_dist ({} > {})", clouds.min_fog_dist, clouds.max_fog_dist),
+        );
+        check(clouds.max_fog_dist > 0.0, "clouds", "max_fog_dist", format!("must be positive, got {}", clouds.max_fog_dist));
+        check(clouds.step_size > 0.0, "clouds", "step_size", format!("must be positive, got {}", clouds.step_size));
+        check(clouds.light_step_size > 0.0, "clouds", "light_step_size", format!("must be positive, got {}", clouds.light_step_size));
+        match clouds.phase_function {
+            PhaseFunction::HenyeyGreenstein { g } | PhaseFunction::CornetteShanks { g } => {
+                check(g.abs() < 1.0, "clouds", "phase_g", format!("must be between -1 and 1 exclusive, got {}", g));
+            },
+            PhaseFunction::DualLobe { forward_g, backward_g, weight } => {
+                check(forward_g.abs() < 1.0, "clouds", "phase_g", format!("must be between -1 and 1 exclusive, got {}", forward_g));
+                check(backward_g.abs() < 1.0, "clouds", "phase_backward_g", format!("must be between -1 and 1 exclusive, got {}", backward_g));
+                check((0.0..=1.0).contains(&weight), "clouds", "phase_forward_weight", format!("must be between 0 and 1, got {}", weight));
+            },
+            PhaseFunction::Legacy | PhaseFunction::Isotropic => {},
+        }
+
+        let background = &self.background;
+        check(background.sun_brightness > 0.0, "background", "sun_brightness", format!("must be positive, got {}", background.sun_brightness));
+        check(
+            (0.0..2.0).contains(&background.sun_size),
+            "background", "sun_size",
+            format!("must be at least 0 and below 2, got {}", background.sun_size),
+        );
+        if background.sky_model == SkyModel::Gradient {
+            check(
+                background.sky_colors.len() >= 2,
+                "background", "sky_colors",
+                format!("at least 2 colors are needed for the gradient, got {}", background.sky_colors.len()),
+            );
+        }
+        if background.sky_model == SkyModel::Atmosphere {
+            let atmosphere = &background.atmosphere;
+            check(background.sky_intensity >= 0.0, "background", "sky_intensity", format!("must not be negative, got {}", background.sky_intensity));
+            check(atmosphere.planet_radius > 0.0, "background", "planet_radius", format!("must be positive, got {}", atmosphere.planet_radius));
+            check(atmosphere.atmosphere_height > 0.0, "background", "atmosphere_height", format!("must be positive, got {}", atmosphere.atmosphere_height));
+            check(atmosphere.rayleigh_scale_height > 0.0, "background", "rayleigh_scale_height", format!("must be positive, got {}", atmosphere.rayleigh_scale_height));
+            check(atmosphere.mie_scale_height > 0.0, "background", "mie_scale_height", format!("must be positive, got {}", atmosphere.mie_scale_height));
+            check(atmosphere.turbidity >= 1.0, "background", "turbidity", format!("must be at least 1, got {}", atmosphere.turbidity));
+            check(atmosphere.mie_g.abs() < 1.0, "background", "mie_g", format!("must be between -1 and 1 exclusive, got {}", atmosphere.mie_g));
+        }
+        if background.sky_model == SkyModel::Preetham || background.sky_model == SkyModel::HosekWilkie {
+            check(background.sky_intensity >= 0.0, "background", "sky_intensity", format!("must not be negative, got {}", background.sky_intensity));
+            check(
+                (1.0..=10.0).contains(&background.atmosphere.turbidity),
+                "background", "turbidity",
+                format!("must be between 1 and 10 for the analytic sky models, got {}", background.atmosphere.turbidity),
+            );
+        }
+        if background.sky_model == SkyModel::HosekWilkie {
+            check((0.0..=1.0).contains(&background.ground_albedo), "background", "ground_albedo", format!("must be between 0 and 1, got {}", background.ground_albedo));
+            check(
+                background.hosek_wilkie_data.is_some(),
+                "background", "hosek_wilkie_data",
+                "the hosek_wilkie sky model needs the path of ArHosekSkyModelData_RGB.h from the reference implementation".to_string(),
+            );
+        }
+
+        let sun = &self.sun;
+        if sun.mode == SunMode::Location {
+            check((-90.0..=90.0).contains(&sun.location.latitude), "sun", "latitude", format!("must be between -90 and 90 degrees, got {}", sun.location.latitude));
+            check((-180.0..=180.0).contains(&sun.location.longitude), "sun", "longitude", format!("must be between -180 and 180 degrees, got {}", sun.location.longitude));
+            check(
+                (-12.0..=14.0).contains(&sun.location.date_time.utc_offset),
+                "sun", "utc_offset",
+                format!("must be between -12 and 14 hours, got {}", sun.location.date_time.utc_offset),
+            );
+        }
+
+        let night = &self.night;
+        if night.enabled {
+            check(
+                self.sun.mode == SunMode::Location,
+                "night", "enabled",
+                "the night sky is placed for the [sun] location, date and time, so it needs [sun] mode = location".to_string(),
+            );
+            check((0.0..2.0).contains(&night.moon_size), "night", "moon_size", format!("must be at least 0 and below 2, got {}", night.moon_size));
+            check(night.moon_brightness >= 0.0, "night", "moon_brightness", format!("must not be negative, got {}", night.moon_brightness));
+            check(night.star_size > 0.0 && night.star_size < 0.1, "night", "star_size", format!("must be above 0 and below 0.1 radians, got {}", night.star_size));
+            check(night.star_brightness >= 0.0, "night", "star_brightness", format!("must not be negative, got {}", night.star_brightness));
+            check(night.milky_way_brightness >= 0.0, "night", "milky_way_brightness", format!("must not be negative, got {}", night.milky_way_brightness));
+        }
+
+        let quality = &self.quality;
+        check(quality.pixel_width > 0, "quality", "pixel_width", "must be at least 1".to_string());
+        check(quality.bundle_size > 0, "quality", "bundle_size", "must be at least 1".to_string());
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(SkyboxError::Invalid(issues))
+        }
+    }
+}
+
+// ---------- INI loading and overrides ----------
+
+pub fn read_ini<P: AsRef<Path>>(path: P) -> Result<Ini, SkyboxError> {
+    let path = path.as_ref();
+    let config_str = fs::read_to_string(path).map_err(|source| SkyboxError::Io { path: path.to_path_buf(), source })?;
+
+    parse_ini(&config_str)
+}
+
+pub fn parse_ini(config_str: &str) -> Result<Ini, SkyboxError> {
+    let mut config = Ini::new();
+    config.read(config_str.to_string()).map_err(SkyboxError::Syntax)?;
+
+    Ok(config)
+}
+
+/// Keys of the `[clouds]` section that describe a layer, and so can also be given in `[clouds.<name>]` sections.
+const LAYER_KEYS: &[&str] = &[
+    "min_height", "max_height", "cloud_threshold", "cloud_softness", "noise_levels", "noise_scale", "cloud_seed", "density",
+    "height_profile", "noise_type", "octave_weights", "warp", "warp_strength", "warp_scale", "coverage", "coverage_image",
+    "coverage_scale", "coverage_offset_x", "coverage_offset_z", "coverage_tiling", "coverage_strength", "coverage_profile",
+    "wind_x", "wind_z", "evolution",
+];
+
+/// Every other key the configuration is read from, by section.
+const KNOWN_KEYS: &[(&str, &[&str])] = &[
+    ("images", &["image_width", "image_height", "render_north_only", "raw_color", "projection", "layout", "convention", "format", "exr_precision", "exr_compression"]),
+    ("slices", &["use_multithreading", "tile_size", "thread_count"]),
+    ("clouds", &[
+        "time", "extinction", "min_fog_dist", "max_fog_dist", "step_size", "step_count", "direct_sun", "light_step_count", "light_step_size",
+        "phase_function", "phase_g", "phase_backward_g", "phase_forward_weight",
+    ]),
+    ("background", &[
+        "sun_brightness", "sun_size", "sun_angle_phi", "sun_angle_theta", "sun_color", "sky_colors", "ground_color", "sky_model", "sky_intensity",
+        "planet_radius", "atmosphere_height", "rayleigh_scale_height", "mie_scale_height", "turbidity", "mie_g", "ozone", "multiple_scattering",
+        "ground_albedo", "hosek_wilkie_data",
+    ]),
+    ("sun", &["mode", "latitude", "longitude", "date", "time", "utc_offset"]),
+    ("night", &["enabled", "moon_size", "moon_color", "moon_brightness", "star_count", "star_seed", "star_catalog", "star_size", "star_brightness", "milky_way", "milky_way_brightness", "sky_color"]),
+    ("quality", &["renderer_seed", "pixel_width", "bundle_size"]),
+];
+
+/// Whether `SkyboxConfig::from_ini` reads the key, whether or not a given file sets it.
+fn is_known_key(section: &str, key: &str) -> bool {
+    let layer_section = section == "clouds" || section.strip_prefix("clouds.").is_some_and(|name| !name.is_empty());
+
+    (layer_section && LAYER_KEYS.contains(&key)) || KNOWN_KEYS.iter().any(|&(known_section, keys)| known_section == section && keys.contains(&key))
+}
+
+/// Applies a `section.key=value` assignment on top of a loaded configuration. Section names may contain dots,
+/// as in `clouds.cirrus.min_height=300`, which adds the layer if the file does not have it.
+/// The key must be one the configuration is read from so that typos are reported instead of being silently ignored,
+/// but it may be missing from the file, where it would take its default.
+pub fn apply_override(config: &mut Ini, assignment: &str) -> Result<(), SkyboxError> {
+    let invalid = |reason: &str| SkyboxError::InvalidOverride { assignment: assignment.to_string(), reason: reason.to_string() };
+
+    let (name, value) = assignment.split_once('=').ok_or_else(|| invalid("expected section.key=value"))?;
+    let (section, key) = name.trim().rsplit_once('.').ok_or_else(|| invalid("expected section.key=value"))?;
+    let (section, key) = (section.trim().to_lowercase(), key.trim().to_lowercase());
+
+    if !is_known_key(&section, &key) {
+        return Err(invalid(&format!("there is no key \"{}\" in section [{}]", key, section)));
+    }
+
+    config.set(&section, &key, Some(value.trim().to_string()));
+
+    Ok(())
+}
+
+// ---------- Typed INI getters ----------
+
+fn get_str(config: &Ini, section: &str, key: &str) -> Result<String, SkyboxError> {
+    config.get(section, key).ok_or_else(|| SkyboxError::MissingKey { section: section.to_string(), key: key.to_string() })
+}
+
+fn wrong_type(section: &str, key: &str, value: &str, expected: &'static str) -> SkyboxError {
+    SkyboxError::WrongType { section: section.to_string(), key: key.to_string(), value: value.to_string(), expected }
+}
+
+fn get_uint<T: TryFrom<u64>>(config: &Ini, section: &str, key: &str) -> Result<T, SkyboxError> {
+    let value = get_str(config, section, key)?;
+    let uint = value.parse::<u64>().map_err(|_| wrong_type(section, key, &value, "an unsigned integer"))?;
+
+    T::try_from(uint).map_err(|_| SkyboxError::OutOfRange {
+        section: section.to_string(),
+        key: key.to_string(),
+        value,
+        reason: "too large for this setting".to_string(),
+    })
+}
+
+/// Reads an optional unsigned integer, falling back to `default` when the key is missing.
+fn get_uint_or<T: TryFrom<u64>>(config: &Ini, section: &str, key: &str, default: T) -> Result<T, SkyboxError> {
+    match config.get(section, key) {
+        None => Ok(default),
+        Some(_) => get_uint(config, section, key),
+    }
+}
+
+fn get_float(config: &Ini, section: &str, key: &str) -> Result<f64, SkyboxError> {
+    let value = get_str(config, section, key)?;
+    let float = value.parse::<f64>().map_err(|_| wrong_type(section, key, &value, "a number"))?;
+
+    if !float.is_finite() {
+        return Err(SkyboxError::OutOfRange { section: section.to_string(), key: key.to_string(), value, reason: "must be a finite number".to_string() });
+    }
+
+    Ok(float)
+}
+
+/// Reads an optional number, falling back to `default` when the key is missing.
+fn get_float_or(config: &Ini, section: &str, key: &str, default: f64) -> Result<f64, SkyboxError> {
+    match config.get(section, key) {
+        None => Ok(default),
+        Some(_) => get_float(config, section, key),
+    }
+}
+
+fn get_bool(config: &Ini, section: &str, key: &str) -> Result<bool, SkyboxError> {
+    let value = get_str(config, section, key)?;
+
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err(wrong_type(section, key, &value, "a boolean (true or false)")),
+    }
+}
+
+/// Reads an optional named option, falling back to `default` so that older configuration files keep working.
+fn get_choice<T>(config: &Ini, section: &str, key: &str, default: T, from_name: fn(&str) -> Option<T>, expected: &'static str) -> Result<T, SkyboxError> {
+    match config.get(section, key) {
+        None => Ok(default),
+        Some(value) => from_name(&value.to_lowercase()).ok_or_else(|| wrong_type(section, key, &value, expected)),
+    }
+}
+
+/// Reads an optional height profile, given either by preset name or as `height:multiplier` points.
+fn get_height_profile(config: &Ini, section: &str, key: &str, default: HeightProfile) -> Result<HeightProfile, SkyboxError> {
+    match config.get(section, key) {
+        None => Ok(default),
+        Some(value) => HeightProfile::from_name(&value.to_lowercase())
+            .or_else(|| HeightProfile::parse_curve(&value))
+            .ok_or_else(|| wrong_type(section, key, &value, "uniform, cumulus, stratus, cumulonimbus or increasing height:multiplier points between 0 and 1")),
+    }
+}
+
+/// Like `get_height_profile`, but an empty value means no profile.
+fn get_optional_height_profile(config: &Ini, section: &str, key: &str, default: Option<HeightProfile>) -> Result<Option<HeightProfile>, SkyboxError> {
+    match config.get(section, key) {
+        None => Ok(default),
+        Some(value) if value.trim().is_empty() => Ok(None),
+        Some(_) => get_height_profile(config, section, key, HeightProfile::Uniform).map(Some),
+    }
+}
+
+/// Reads an optional path, falling back to `default` when the key is missing. An empty value means no path.
+fn get_path_or(config: &Ini, section: &str, key: &str, default: Option<PathBuf>) -> Option<PathBuf> {
+    match config.get(section, key) {
+        None => default,
+        Some(path) => Some(path).filter(|path| !path.is_empty()).map(PathBuf::from),
+    }
+}
+
+/// Reads an optional boolean, falling back to `default` when the key is missing.
+fn get_bool_or(config: &Ini, section: &str, key: &str, default: bool) -> Result<bool, SkyboxError> {
+    match config.get(section, key) {
+        None => Ok(default),
+        Some(_) => get_bool(config, section, key),
+    }
+}
+
+fn parse_color(section: &str, key: &str, value: &str) -> Result<Color, SkyboxError> {
+    value.parse().map_err(|_| SkyboxError::MalformedColor { section: section.to_string(), key: key.to_string(), value: value.to_string() })
+}
+
+fn get_color(config: &Ini, section: &str, key: &str) -> Result<Color, SkyboxError> {
+    parse_color(section, key, &get_str(config, section, key)?)
+}
+
+/// Reads an optional color, falling back to `default` when the key is missing.
+fn get_color_or(config: &Ini, section: &str, key: &str, default: Color) -> Result<Color, SkyboxError> {
+    match config.get(section, key) {
+        None => Ok(default),
+        Some(_) => get_color(config, section, key),
+    }
+}
+
+fn get_colors(config: &Ini, section: &str, key: &str) -> Result<Vec<Color>, SkyboxError> {
+    get_str(config, section, key)?.split(',').map(|str| parse_color(section, key, str.trim())).collect()
+}
+
+/// Reads an optional comma separated list of numbers, falling back to `default` when the key is missing.
+/// An empty value gives an empty list.
+fn get_floats_or(config: &Ini, section: &str, key: &str, default: Vec<f64>) -> Result<Vec<f64>, SkyboxError> {
+    match config.get(section, key) {
+        None => Ok(default),
+        Some(value) if value.trim().is_empty() => Ok(Vec::new()),
+        Some(value) => value
+            .split(',')
+            .map(|number| number.trim().parse::<f64>().ok().filter(|number| number.is_finite()).ok_or_else(|| wrong_type(section, key, &value, "comma separated numbers")))
+            .collect(),
+    }
+}
+
+impl Default for SkyboxConfig {
+    /// The same values as the *config.ini* shipped with the repository, except for the `[sun]` place and time,
+    /// which the file gives as an example for `mode = location`.
+    fn default() -> Self {
+        SkyboxConfig {
+            images: ImagesConfig {
+                image_width: 256,
+                image_height: 256,
+                render_north_only: false,
+                raw_color: false,
+                projection: Projection::Cubemap,
+                layout: CubemapLayout::Separate,
+                convention: CubemapConvention::DirectX,
+                format: ImageFormat::Png,
+                exr_precision: ExrPrecision::Half,
+                exr_compression: ExrCompression::Zip,
+            },
+            slices: SlicesConfig {
+                use_multithreading: true,
+                tile_size: 16,
+                thread_count: 0,
+            },
+            clouds: CloudsConfig {
+                layers: vec![CloudLayerConfig {
+                    section: "clouds".to_string(),
+                    min_height: 64.0,
+                    max_height: 128.0,
+                    cloud_threshold: 0.6,
+                    cloud_softness: 0.0,
+                    noise_levels: 12,
+                    noise_scale: 1.0,
+                    cloud_seed: 0,
+                    density: 1.0,
+                    height_profile: HeightProfile::Uniform,
+                    noise_type: NoiseType::Value,
+                    octave_weights: Vec::new(),
+                    warp: WarpType::None,
+                    warp_strength: 16.0,
+                    warp_scale: 64.0,
+                    coverage: CoverageMode::None,
+                    coverage_image: None,
+                    coverage_scale: 4096.0,
+                    coverage_offset_x: 0.0,
+                    coverage_offset_z: 0.0,
+                    coverage_tiling: true,
+                    coverage_strength: 0.3,
+                    coverage_profile: None,
+                    wind_x: 0.0,
+                    wind_z: 0.0,
+                    evolution: 0.0,
+                }],
+                time: 0.0,
+                extinction: 1.0,
+                min_fog_dist: 250.0,
+                max_fog_dist: 1000.0,
+                step_size: 8.0,
+                step_count: 16,
+                direct_sun: true,
+                light_step_count: 8,
+                light_step_size: 8.0,
+                phase_function: PhaseFunction::HenyeyGreenstein { g: 0.6 },
+            },
+            background: BackgroundConfig {
+                sun_brightness: 20.0,
+                sun_size: 0.2,
+                sun_angle_phi: 0.9553166181245093,
+                sun_angle_theta: FRAC_PI_4,
+                sun_color: Color::from_srgb_8(0xff, 0xe1, 0xa0),
+                sky_colors: vec![Color::from_srgb_8(0x9b, 0xe1, 0xff), Color::from_srgb_8(0x3f, 0x89, 0xe1)],
+                ground_color: Color::from_srgb_8(0x9b, 0x9b, 0x9b),
+                sky_model: SkyModel::Gradient,
+                sky_intensity: 20.0,
+                atmosphere: AtmosphereParameters::default(),
+                ground_albedo: 0.3,
+                hosek_wilkie_data: None,
+            },
+            sun: SunConfig {
+                mode: SunMode::Angles,
+                location: SunLocation {
+                    latitude: 0.0,
+                    longitude: 0.0,
+                    date_time: DateTime { year: 2022, month: 6, day: 21, hour: 12, minute: 0, second: 0, utc_offset: 0.0 },
+                },
+            },
+            night: NightConfig {
+                enabled: false,
+                sky_color: Color::from_srgb_8(0x04, 0x08, 0x14),
+                moon_size: 0.08,
+                moon_color: Color::from_srgb_8(0xf4, 0xf1, 0xe8),
+                moon_brightness: 2.0,
+                star_count: 4000,
+                star_seed: 0,
+                star_size: 0.003,
+                star_brightness: 4.0,
+                star_catalog: None,
+                milky_way: true,
+                milky_way_brightness: 0.02,
+            },
+            quality: QualityConfig {
+                renderer_seed: 123,
+                pixel_width: 4,
+                bundle_size: 8,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A configuration file from before any optional key existed.
+    const MINIMAL: &str = "\
+[images]
+image_width = 16
+image_height = 16
+render_north_only = true
+raw_color = false
+[slices]
+use_multithreading = false
+[clouds]
+min_height = 64
+max_height = 128
+cloud_threshold = 0.6
+noise_levels = 12
+noise_scale = 1
+cloud_seed = 0
+min_fog_dist = 250
+max_fog_dist = 1000
+step_size = 8
+step_count = 16
+[background]
+sun_brightness = 20
+sun_size = 0.2
+sun_angle_phi = 0.9553166181245093
+sun_angle_theta = 0.7853981633974483
+sun_color = ffe1a0
+sky_colors = 9be1ff, 3f89e1
+ground_color = 9b9b9b
+[quality]
+renderer_seed = 0
+pixel_width = 1
+bundle_size = 8
+";
+
+    fn shipped_ini() -> Ini {
+        read_ini(Path::new(env!("CARGO_MANIFEST_DIR")).join("config.ini")).unwrap()
+    }
+
+    #[test]
+    fn known_keys_match_shipped_config() {
+        let ini = shipped_ini();
+
+        for (section, keys) in ini.get_map_ref() {
+            for key in keys.keys() {
+                assert!(is_known_key(section, key), "[{}] {} is not a known key", section, key);
+            }
+        }
+        for &(section, keys) in KNOWN_KEYS {
+            for key in keys {
+                assert!(ini.get(section, key).is_some(), "[{}] {} is missing from config.ini", section, key);
+            }
+        }
+        for key in LAYER_KEYS {
+            assert!(ini.get("clouds", key).is_some(), "[clouds] {} is missing from config.ini", key);
+        }
+    }
+
+    #[test]
+    fn override_sets_keys_missing_from_the_file() {
+        let mut ini = parse_ini(MINIMAL).unwrap();
+        apply_override(&mut ini, "images.format=hdr").unwrap();
+        apply_override(&mut ini, "clouds.wind_x = 5").unwrap();
+        apply_override(&mut ini, "clouds.cirrus.min_height=400").unwrap();
+        apply_override(&mut ini, "clouds.cirrus.max_height=420").unwrap();
+
+        let config = SkyboxConfig::from_ini(&ini).unwrap();
+        assert_eq!(config.images.format, ImageFormat::Hdr);
+        assert_eq!(config.clouds.layers[0].wind_x, 5.0);
+        assert_eq!(config.clouds.layers.len(), 2);
+        assert_eq!((config.clouds.layers[1].min_height, config.clouds.layers[1].max_height), (400.0, 420.0));
+        // The new layer inherits the wind of [clouds].
+        assert_eq!(config.clouds.layers[1].wind_x, 5.0);
+    }
+
+    #[test]
+    fn override_rejects_unknown_keys() {
+        let mut ini = parse_ini(MINIMAL).unwrap();
+
+        for assignment in ["images.image_widht=4", "image_width=4", "images.image_width", "imagery.image_width=4", "clouds.cirrus.extinction=2", "clouds..min_height=1", "images.min_height=1"] {
+            assert!(matches!(apply_override(&mut ini, assignment), Err(SkyboxError::InvalidOverride { .. })), "{} was accepted", assignment);
+        }
+        assert_eq!(ini.get("images", "image_width").as_deref(), Some("16"));
+    }
+
+    #[test]
+    fn default_and_shipped_configs_are_valid() {
+        SkyboxConfig::default().validate().unwrap();
+        SkyboxConfig::from_ini(&shipped_ini()).unwrap().validate().unwrap();
+        SkyboxConfig::from_ini_str(MINIMAL).unwrap().validate().unwrap();
+    }
+
+    #[test]
+    fn default_matches_shipped_config() {
+        let shipped = SkyboxConfig::from_ini(&shipped_ini()).unwrap();
+
+        assert_eq!(format!("{:#?}", SkyboxConfig::default()), format!("{:#?}", shipped));
+    }
+
+    #[test]
+    fn sun_location_needs_the_place_and_time() {
+        let location = "[sun]\nmode = location\nlatitude = 10\nlongitude = 20\ndate = 2022-03-20\ntime = 6:00\n";
+        let config = SkyboxConfig::from_ini_str(&format!("{}{}", MINIMAL, location)).unwrap();
+        assert_eq!(config.sun.location.date_time.utc_offset, 0.0);
+
+        for key in ["latitude", "longitude", "date", "time"] {
+            let ini: String = location.lines().filter(|line| !line.starts_with(key)).map(|line| format!("{}\n", line)).collect();
+
+            match SkyboxConfig::from_ini_str(&format!("{}{}", MINIMAL, ini)) {
+                Err(SkyboxError::MissingKey { section, key: missing }) => assert_eq!((section.as_str(), missing.as_str()), ("sun", key)),
+                other => panic!("without {}: {:?}", key, other),
+            }
+        }
+    }
+
+    #[test]
+    fn validation_reports_every_issue() {
+        let mut config = SkyboxConfig::default();
+        config.images.image_width = 0;
+        config.clouds.extinction = -1.0;
+        config.clouds.min_fog_dist = 2000.0;
+        config.clouds.layers[0].cloud_softness = -0.5;
+
+        let issues = match config.validate() {
+            Err(SkyboxError::Invalid(issues)) => issues,
+            other => panic!("expected issues, got {:?}", other),
+        };
+        let keys: Vec<(&str, &str)> = issues.iter().map(|issue| (issue.section.as_str(), issue.key)).collect();
+
+        assert_eq!(keys.len(), 4, "{:?}", issues);
+        for key in [("images", "image_width"), ("clouds", "extinction"), ("clouds", "min_fog_dist"), ("clouds", "cloud_softness")] {
+            assert!(keys.contains(&key), "{:?} not reported in {:?}", key, issues);
+        }
+    }
+
+    #[test]
+    fn validation_names_the_layer_section() {
+        let mut ini = parse_ini(MINIMAL).unwrap();
+        apply_override(&mut ini, "clouds.high.min_height=100").unwrap();
+        apply_override(&mut ini, "clouds.high.max_height=90").unwrap();
+
+        match SkyboxConfig::from_ini(&ini).unwrap().validate() {
+            Err(SkyboxError::Invalid(issues)) => assert!(issues.iter().any(|issue| issue.section == "clouds.high" && issue.key == "min_height"), "{:?}", issues),
+            other => panic!("expected issues, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn night_needs_the_sun_location() {
+        let mut config = SkyboxConfig::default();
+        config.night.enabled = true;
+
+        match config.validate() {
+            Err(SkyboxError::Invalid(issues)) => assert!(issues.iter().any(|issue| issue.section == "night" && issue.key == "enabled"), "{:?}", issues),
+            other => panic!("expected issues, got {:?}", other),
+        }
+
+        config.sun.mode = SunMode::Location;
+        config.validate().unwrap();
+    }
+}
@@ -0,0 +1,216 @@
+// Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
+
+use std::fs::File;
+use std::path::Path;
+use crate::error::SkyboxError;
+use crate::fast_rng::hash_i32;
+
+/// Octaves of the procedural coverage, each half the size and weight of the one before.
+const PROCEDURAL_OCTAVES: u32 = 4;
+
+/// Where the coverage of a cloud layer comes from, as chosen by the `coverage` configuration key.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CoverageMode {
+    /// The same coverage everywhere.
+    None,
+    /// A PNG image laid over the ground.
+    Image,
+    /// Smooth random patches of cloudy and clear sky.
+    Procedural,
+}
+
+impl CoverageMode {
+    pub fn from_name(name: &str) -> Option<CoverageMode> {
+        match name {
+            "none" => Some(CoverageMode::None),
+            "image" => Some(CoverageMode::Image),
+            "procedural" => Some(CoverageMode::Procedural),
+            _ => None,
+        }
+    }
+}
+
+/// Coverage and cloud type in [0, 1] over a unit square.
+#[derive(Debug, Clone)]
+pub enum CoverageMap {
+    /// Red (or gray) is the coverage and blue the cloud type. Rows run from north at the top to south, and columns
+    /// from west to east, like a map.
+    Image { width: usize, height: usize, texels: Vec<[f64; 2]> },
+    /// Two independent sums of value noise octaves, one for the coverage and one for the cloud type.
+    Procedural { seed: i32 },
+}
+
+impl CoverageMap {
+    /// Reads a PNG image of any color type. The values are used as they are, without sRGB decoding.
+    pub fn read_png(path: &Path) -> Result<CoverageMap, SkyboxError> {
+        let invalid = |reason: String| SkyboxError::InvalidData { path: path.to_path_buf(), reason };
+
+        let file = File::open(path).map_err(|source| SkyboxError::Io { path: path.to_path_buf(), source })?;
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+
+        let (info, mut reader) = decoder.read_info().map_err(|error| invalid(error.to_string()))?;
+        let mut buffer = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buffer).map_err(|error| invalid(error.to_string()))?;
+
+        let channels = match info.color_type {
+            png::ColorType::Grayscale => 1,
+            png::ColorType::GrayscaleAlpha => 2,
+            png::ColorType::RGB => 3,
+            png::ColorType::RGBA => 4,
+            png::ColorType::Indexed => return Err(invalid("the palette could not be expanded".to_string())),
+        };
+        let blue = if channels >= 3 { 2 } else { 0 };
+
+        let (width, height) = (info.width as usize, info.height as usize);
+        let texels = (0..width * height)
+            .map(|index| {
+                let (row, column) = (index / width, index % width);
+                let texel = &buffer[row * info.line_size + column * channels..];
+
+                [texel[0] as f64 / 255.0, texel[blue] as f64 / 255.0]
+            })
+            .collect();
+
+        Ok(CoverageMap::Image { width, height, texels })
+    }
+
+    pub fn procedural(seed: i32) -> CoverageMap {
+        CoverageMap::Procedural { seed }
+    }
+
+    /// Coverage and cloud type at a point of the unit square, u running east and v north. When `tiling`, the
+    /// filtering wraps around the edges like the map does.
+    fn sample(&self, u: f64, v: f64, tiling: bool) -> [f64; 2] {
+        match self {
+            CoverageMap::Image { width, height, texels } => {
+                // Bilinear filtering between texel centers, holding the edge texels beyond them unless tiling.
+                let (x, y) = (u * *width as f64 - 0.5, (1.0 - v) * *height as f64 - 0.5);
+                let (x, y) = if tiling {
+                    (x.rem_euclid(*width as f64), y.rem_euclid(*height as f64))
+                } else {
+                    (x.clamp(0.0, (*width - 1) as f64), y.clamp(0.0, (*height - 1) as f64))
+                };
+
+                let (x0, y0) = ((x as usize).min(*width - 1), (y as usize).min(*height - 1));
+                let (x1, y1) = if tiling {
+                    ((x0 + 1) % *width, (y0 + 1) % *height)
+                } else {
+                    ((x0 + 1).min(*width - 1), (y0 + 1).min(*height - 1))
+                };
+                let (ax, ay) = (x - x0 as f64, y - y0 as f64);
+
+                let texel = |x: usize, y: usize| texels[y * *width + x];
+                let lerp = |a: [f64; 2], b: [f64; 2], amount: f64| [a[0] + (b[0] - a[0]) * amount, a[1] + (b[1] - a[1]) * amount];
+
+                lerp(lerp(texel(x0, y0), texel(x1, y0), ax), lerp(texel(x0, y1), texel(x1, y1), ax), ay)
+            },
+            CoverageMap::Procedural { seed } => [value_noise(*seed, u, v), value_noise(type_seed(*seed), u, v)],
+        }
+    }
+}
+
+/// A coverage map laid over the horizontal xz-plane.
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    map: CoverageMap,
+    /// Width and depth of the area the map covers.
+    scale: f64,
+    /// Center of the map.
+    offset_x: f64,
+    offset_z: f64,
+    /// Repeat the map beyond its edges instead of stretching the edge texels.
+    tiling: bool,
+}
+
+impl Coverage {
+    pub fn new(map: CoverageMap, scale: f64, offset_x: f64, offset_z: f64, tiling: bool) -> Self {
+        Coverage { map, scale, offset_x, offset_z, tiling }
+    }
+
+    /// Coverage and cloud type at a point of the plane.
+    pub fn sample(&self, x: f64, z: f64) -> [f64; 2] {
+        let u = (x - self.offset_x) / self.scale + 0.5;
+        let v = (z - self.offset_z) / self.scale + 0.5;
+
+        if self.tiling {
+            self.map.sample(u.rem_euclid(1.0), v.rem_euclid(1.0), true)
+        } else {
+            self.map.sample(u, v, false)
+        }
+    }
+}
+
+/// Octaves of smoothly interpolated random values in [0, 1] that repeat over the unit square.
+fn value_noise(seed: i32, u: f64, v: f64) -> f64 {
+    let mut sum = 0.0;
+    let mut weight = 0.5;
+    let mut cells = 4;
+
+    for octave in 0..PROCEDURAL_OCTAVES {
+        let (x, y) = (u.rem_euclid(1.0) * cells as f64, v.rem_euclid(1.0) * cells as f64);
+        let (x0, y0) = (x as i32, y as i32);
+        let (ax, ay) = (smooth(x - x0 as f64), smooth(y - y0 as f64));
+
+        let value = |x: i32, y: i32| hash(seed.wrapping_add(octave as i32), x.rem_euclid(cells), y.rem_euclid(cells));
+        let bottom = value(x0, y0) + (value(x0 + 1, y0) - value(x0, y0)) * ax;
+        let top = value(x0, y0 + 1) + (value(x0 + 1, y0 + 1) - value(x0, y0 + 1)) * ax;
+
+        sum += (bottom + (top - bottom) * ay) * weight;
+        weight /= 2.0;
+        cells *= 2;
+    }
+
+    // The weights add up to 1 - 0.5^octaves.
+    sum / (1.0 - 0.5f64.powi(PROCEDURAL_OCTAVES as i32))
+}
+
+/// Seed of the procedural cloud type. The octaves of a sum use consecutive seeds, so the type's seed is salted and
+/// hashed rather than following the coverage's.
+fn type_seed(seed: i32) -> i32 {
+    hash_i32(seed ^ 0x5bd1e995)
+}
+
+fn smooth(n: f64) -> f64 {
+    n * n * (3.0 - 2.0 * n)
+}
+
+fn hash(seed: i32, x: i32, y: i32) -> f64 {
+    let mut val = hash_i32(seed);
+    val = hash_i32(val.wrapping_add(x));
+    val = hash_i32(val.wrapping_add(y));
+
+    (val as f64 + 2147483648.0) / 4294967295.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiled_image_wraps_across_the_edges() {
+        // One row going from clear in the west to overcast in the east.
+        let map = CoverageMap::Image { width: 4, height: 1, texels: vec![[0.0, 0.0], [1.0 / 3.0, 0.0], [2.0 / 3.0, 0.0], [1.0, 0.0]] };
+        let (tiled, stretched) = (Coverage::new(map.clone(), 4.0, 0.0, 0.0, true), Coverage::new(map, 4.0, 0.0, 0.0, false));
+
+        // The western and eastern edges are halfway between the first and the last texel.
+        for x in [-2.0, 2.0, 6.0] {
+            assert!((tiled.sample(x, 0.0)[0] - 0.5).abs() < 1e-12);
+        }
+        assert_eq!(stretched.sample(-2.0, 0.0)[0], 0.0);
+        assert_eq!(stretched.sample(2.0, 0.0)[0], 1.0);
+
+        // No seam: nearby points on both sides of an edge are close.
+        assert!((tiled.sample(2.0 - 1e-6, 0.0)[0] - tiled.sample(2.0 + 1e-6, 0.0)[0]).abs() < 1e-5);
+    }
+
+    #[test]
+    fn procedural_sums_use_separate_seeds() {
+        let octaves = PROCEDURAL_OCTAVES as i32;
+
+        for seed in -1000..1000 {
+            let type_seed = type_seed(seed);
+            assert!((0..octaves).all(|octave| type_seed.wrapping_add(octave).wrapping_sub(seed).unsigned_abs() >= octaves as u32), "seed {}", seed);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
+
+use std::{error, fmt, io};
+use std::path::PathBuf;
+
+/// Everything that can go wrong while loading a configuration or writing output images.
+#[derive(Debug)]
+pub enum SkyboxError {
+    /// The configuration file is not valid INI.
+    Syntax(String),
+    MissingKey {
+        section: String,
+        key: String,
+    },
+    /// The value could not be parsed as the type the key requires.
+    WrongType {
+        section: String,
+        key: String,
+        value: String,
+        expected: &'static str,
+    },
+    MalformedColor {
+        section: String,
+        key: String,
+        value: String,
+    },
+    OutOfRange {
+        section: String,
+        key: String,
+        value: String,
+        reason: String,
+    },
+    /// A command-line `section.key=value` override is malformed or names an unknown key.
+    InvalidOverride {
+        assignment: String,
+        reason: String,
+    },
+    /// A value passed to a library constructor, such as `Cloud::new`, cannot be used.
+    InvalidArgument {
+        name: &'static str,
+        reason: String,
+    },
+    /// The configuration parsed but describes a scene that cannot be rendered. Lists every problem found.
+    Invalid(Vec<ConfigIssue>),
+    /// An output template has an unclosed or unknown placeholder.
+    InvalidTemplate {
+        template: String,
+        reason: String,
+    },
+    /// The output file exists and the overwrite policy does not allow replacing it.
+    OutputExists {
+        path: PathBuf,
+    },
+    /// The render was stopped through its cancellation token.
+    Cancelled,
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// A data file read while setting up the scene, such as sky model coefficients, is malformed.
+    InvalidData {
+        path: PathBuf,
+        reason: String,
+    },
+    Encoding {
+        path: PathBuf,
+        source: Box<dyn error::Error + Send + Sync>,
+    },
+}
+
+/// A single semantic problem found by `SkyboxConfig::validate`.
+#[derive(Debug, Clone)]
+pub struct ConfigIssue {
+    pub section: String,
+    pub key: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}: {}", self.section, self.key, self.message)
+    }
+}
+
+impl fmt::Display for SkyboxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkyboxError::Syntax(message) => write!(f, "configuration syntax error: {}", message),
+            SkyboxError::MissingKey { section, key } => write!(f, "[{}] {}: missing key", section, key),
+            SkyboxError::WrongType { section, key, value, expected } => write!(f, "[{}] {}: \"{}\" is not {}", section, key, value, expected),
+            SkyboxError::MalformedColor { section, key, value } => write!(f, "[{}] {}: \"{}\" is not a 6-digit hex color (e.g. ffe1a0)", section, key, value),
+            SkyboxError::OutOfRange { section, key, value, reason } => write!(f, "[{}] {}: {} is out of range: {}", section, key, value, reason),
+            SkyboxError::InvalidOverride { assignment, reason } => write!(f, "override \"{}\": {}", assignment, reason),
+            SkyboxError::InvalidArgument { name, reason } => write!(f, "invalid {}: {}", name, reason),
+            SkyboxError::Invalid(issues) => {
+                write!(f, "invalid configuration ({} problem{})", issues.len(), if issues.len() == 1 { "" } else { "s" })?;
+                for issue in issues {
+                    write!(f, "\n\t{}", issue)?;
+                }
+                Ok(())
+            },
+            SkyboxError::InvalidTemplate { template, reason } => write!(f, "output template \"{}\": {}", template, reason),
+            SkyboxError::OutputExists { path } => write!(f, "{}: file already exists", path.display()),
+            SkyboxError::Cancelled => write!(f, "rendering was cancelled"),
+            SkyboxError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            SkyboxError::InvalidData { path, reason } => write!(f, "{}: {}", path.display(), reason),
+            SkyboxError::Encoding { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl error::Error for SkyboxError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            SkyboxError::Io { source, .. } => Some(source),
+            SkyboxError::Encoding { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
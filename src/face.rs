@@ -0,0 +1,66 @@
+// Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
+
+use crate::vec3::Vec3;
+use crate::camera::Camera;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Face {
+    North,
+    South,
+    East,
+    West,
+    Up,
+    Down,
+}
+
+impl Face {
+    pub const ALL: [Face; 6] = [Face::North, Face::South, Face::East, Face::West, Face::Up, Face::Down];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Face::North => "north",
+            Face::South => "south",
+            Face::East => "east",
+            Face::West => "west",
+            Face::Up => "up",
+            Face::Down => "down",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Face> {
+        Face::ALL.iter().copied().find(|face| face.name() == name)
+    }
+
+    /// The direction the face looks at. East is +x, up is +y and north is +z.
+    pub fn forward(self) -> Vec3 {
+        match self {
+            Face::North => Vec3::new(0.0, 0.0, 1.0),
+            Face::South => Vec3::new(0.0, 0.0, -1.0),
+            Face::East => Vec3::new(1.0, 0.0, 0.0),
+            Face::West => Vec3::new(-1.0, 0.0, 0.0),
+            Face::Up => Vec3::new(0.0, 1.0, 0.0),
+            Face::Down => Vec3::new(0.0, -1.0, 0.0),
+        }
+    }
+
+    /// The direction that points to the top of the image.
+    pub fn up(self) -> Vec3 {
+        match self {
+            Face::Up | Face::Down => Vec3::new(0.0, 0.0, 1.0),
+            _ => Vec3::new(0.0, 1.0, 0.0),
+        }
+    }
+
+    /// The direction that points to the right side of the image.
+    pub fn right(self) -> Vec3 {
+        self.up().cross(self.forward())
+    }
+
+    pub fn from_forward(forward: Vec3) -> Face {
+        Face::ALL.iter().copied().max_by(|a, b| a.forward().dot(forward).total_cmp(&b.forward().dot(forward))).unwrap()
+    }
+
+    pub fn camera(self) -> Camera {
+        Camera::new(self.forward(), self.up())
+    }
+}
@@ -6,7 +6,8 @@ pub struct Frng {
 
 impl Frng {
     pub fn new(seed: u64) -> Frng {
-        let mut result = Frng {state: seed};
+        // The generator is multiplicative, so an even state would lose its low bits for good.
+        let mut result = Frng {state: seed | 1};
 
         result.next();
         result.next();
@@ -15,6 +16,23 @@ impl Frng {
         result
     }
 
+    /// Seeds a generator from several values, e.g. a pixel position, so that neighbouring keys give unrelated streams.
+    pub fn from_key(key: &[u64]) -> Frng {
+        let mut seed = 0x9e3779b97f4a7c15_u64;
+        for &value in key {
+            seed = Frng::mix(seed ^ value).wrapping_add(0x9e3779b97f4a7c15);
+        }
+
+        Frng::new(Frng::mix(seed))
+    }
+
+    /// SplitMix64 finalizer.
+    fn mix(mut z: u64) -> u64 {
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
     fn next(&mut self) {
         self.state = self.state.wrapping_mul(44485709377909);
     }
@@ -31,4 +49,9 @@ impl Frng {
     pub fn next_double(&mut self, min: f64, max: f64) -> f64 {
         self.next_double_default() * (max - min) + min
     }
-}
\ No newline at end of file
+}
+
+/// Integer hash shared by the cloud noise and the procedural coverage.
+pub(crate) fn hash_i32(x: i32) -> i32 {
+    ((x >> 16) ^ x).wrapping_mul(0x45d9f3b)
+}
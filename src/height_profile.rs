@@ -0,0 +1,83 @@
+// Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
+
+// Preset curves as (height, multiplier) points, height from 0 at the bottom of the layer to 1 at the top.
+const CUMULUS: &[(f64, f64)] = &[(0.0, 0.0), (0.06, 1.0), (0.3, 1.0), (0.6, 0.88), (1.0, 0.6)];
+const STRATUS: &[(f64, f64)] = &[(0.0, 0.0), (0.05, 1.0), (0.2, 1.0), (0.3, 0.0)];
+const CUMULONIMBUS: &[(f64, f64)] = &[(0.0, 0.0), (0.04, 1.0), (0.5, 0.94), (0.75, 1.08), (0.92, 1.12), (1.0, 0.0)];
+
+/// How cloud density changes from the bottom to the top of a layer, as chosen by the `height_profile` key.
+/// The noise is multiplied by the profile before the threshold is applied, so a profile below 1 shrinks the
+/// clouds at that height and one above 1 widens them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeightProfile {
+    /// The same at every height, which gives flat tops and bottoms.
+    Uniform,
+    /// Flat bases and tops that round off and thin out with height.
+    Cumulus,
+    /// A thin flat sheet near the bottom of the layer.
+    Stratus,
+    /// Tall towers that spread into an anvil near the top of the layer.
+    Cumulonimbus,
+    /// Piecewise linear through (height, multiplier) points with increasing heights from 0 to 1.
+    Curve(Vec<(f64, f64)>),
+}
+
+impl HeightProfile {
+    pub fn from_name(name: &str) -> Option<HeightProfile> {
+        match name {
+            "uniform" => Some(HeightProfile::Uniform),
+            "cumulus" => Some(HeightProfile::Cumulus),
+            "stratus" => Some(HeightProfile::Stratus),
+            "cumulonimbus" => Some(HeightProfile::Cumulonimbus),
+            _ => None,
+        }
+    }
+
+    /// Parses a curve of comma separated `height:multiplier` points, such as `0:0, 0.1:1, 1:0.5`. Heights must
+    /// increase and lie between 0 and 1, and multipliers must not be negative.
+    pub fn parse_curve(text: &str) -> Option<HeightProfile> {
+        let points = text
+            .split(',')
+            .map(|point| {
+                let (height, multiplier) = point.split_once(':')?;
+                Some((height.trim().parse::<f64>().ok()?, multiplier.trim().parse::<f64>().ok()?))
+            })
+            .collect::<Option<Vec<(f64, f64)>>>()?;
+
+        let valid = points.iter().all(|&(height, multiplier)| (0.0..=1.0).contains(&height) && multiplier >= 0.0 && multiplier.is_finite())
+            && points.windows(2).all(|pair| pair[0].0 < pair[1].0);
+
+        if valid {
+            Some(HeightProfile::Curve(points))
+        } else {
+            None
+        }
+    }
+
+    /// The multiplier at a height from 0 at the bottom of the layer to 1 at the top.
+    pub fn evaluate(&self, height: f64) -> f64 {
+        match self {
+            HeightProfile::Uniform => 1.0,
+            HeightProfile::Cumulus => interpolate(CUMULUS, height),
+            HeightProfile::Stratus => interpolate(STRATUS, height),
+            HeightProfile::Cumulonimbus => interpolate(CUMULONIMBUS, height),
+            HeightProfile::Curve(points) => interpolate(points, height),
+        }
+    }
+}
+
+/// Linear interpolation between the points, holding the first and last values beyond them.
+fn interpolate(points: &[(f64, f64)], height: f64) -> f64 {
+    let (first, last) = (points[0], points[points.len() - 1]);
+    if height <= first.0 {
+        return first.1;
+    }
+    if height >= last.0 {
+        return last.1;
+    }
+
+    let index = points.iter().position(|&(point_height, _)| point_height > height).unwrap_or(points.len() - 1);
+    let ((h0, m0), (h1, m1)) = (points[index - 1], points[index]);
+
+    m0 + (m1 - m0) * (height - h0) / (h1 - h0)
+}
@@ -0,0 +1,347 @@
+// Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
+
+use std::f64::consts::FRAC_PI_2;
+use std::fs;
+use std::path::Path;
+use crate::vec3::Vec3;
+use crate::color::Color;
+use crate::error::SkyboxError;
+use crate::preetham::Preetham;
+
+const PARAMETERS: usize = 9;
+const CONTROL_POINTS: usize = 6;
+const TURBIDITIES: usize = 10;
+const ALBEDOS: usize = 2;
+
+/// Sun elevation in degrees at which the model is normalized to the Preetham model's zenith luminance.
+const NORMALIZATION_ELEVATION: f64 = 35.0;
+
+/// Fitted coefficients of the Hosek-Wilkie model for the red, green and blue channels, as published with the
+/// reference implementation in *ArHosekSkyModelData_RGB.h*. For every albedo and turbidity there are six
+/// Bezier control points over the sun elevation, of the nine distribution parameters and of the zenith radiance.
+#[derive(Debug, Clone)]
+pub struct HosekWilkieData {
+    coefficients: [Vec<f64>; 3],
+    radiances: [Vec<f64>; 3],
+}
+
+impl HosekWilkieData {
+    pub fn from_file(path: &Path) -> Result<HosekWilkieData, SkyboxError> {
+        let text = fs::read_to_string(path).map_err(|source| SkyboxError::Io { path: path.to_path_buf(), source })?;
+
+        HosekWilkieData::parse(&text).map_err(|reason| SkyboxError::InvalidData { path: path.to_path_buf(), reason })
+    }
+
+    /// Reads the `datasetRGB1` to `datasetRGB3` and `datasetRGBRad1` to `datasetRGBRad3` arrays from the C header.
+    pub fn parse(text: &str) -> Result<HosekWilkieData, String> {
+        let text = strip_comments(text);
+
+        let array = |name: &str, length: usize| -> Result<Vec<f64>, String> {
+            let start = text.find(&format!("{}[]", name)).ok_or_else(|| format!("array {} not found", name))?;
+            let body = &text[start..];
+            let open = body.find('{').ok_or_else(|| format!("array {} has no initializer", name))?;
+            let close = body.find('}').ok_or_else(|| format!("array {} is not closed", name))?;
+
+            let values = body[open + 1..close]
+                .split(',')
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(|value| value.parse::<f64>().map_err(|_| format!("array {}: \"{}\" is not a number", name, value)))
+                .collect::<Result<Vec<f64>, String>>()?;
+
+            if values.len() != length {
+                return Err(format!("array {} has {} values, expected {}", name, values.len(), length));
+            }
+
+            Ok(values)
+        };
+
+        let coefficient_length = ALBEDOS * TURBIDITIES * PARAMETERS * CONTROL_POINTS;
+        let radiance_length = ALBEDOS * TURBIDITIES * CONTROL_POINTS;
+
+        Ok(HosekWilkieData {
+            coefficients: [
+                array("datasetRGB1", coefficient_length)?,
+                array("datasetRGB2", coefficient_length)?,
+                array("datasetRGB3", coefficient_length)?,
+            ],
+            radiances: [
+                array("datasetRGBRad1", radiance_length)?,
+                array("datasetRGBRad2", radiance_length)?,
+                array("datasetRGBRad3", radiance_length)?,
+            ],
+        })
+    }
+}
+
+/// The analytic sky model from Hosek and Wilkie, "An Analytic Model for Full Spectral Sky-Dome Radiance",
+/// evaluated in RGB for one sun elevation, turbidity and ground albedo. The dataset's radiance is rescaled so that
+/// with the sun at 35 degrees the zenith is as bright as in the Preetham model, and through it the atmosphere model,
+/// so that one `sky_intensity` suits all three.
+#[derive(Debug, Clone)]
+pub struct HosekWilkie {
+    sun_direction: Vec3,
+    /// The nine distribution parameters A to I per channel, in the reference order (A, B, C, D, E, F, G, I, H).
+    configurations: [[f64; PARAMETERS]; 3],
+    radiances: [f64; 3],
+}
+
+impl HosekWilkie {
+    pub fn new(data: &HosekWilkieData, turbidity: f64, albedo: f64, sun_direction: Vec3) -> Self {
+        let model = HosekWilkie::with_dataset_units(data, turbidity, albedo, sun_direction);
+
+        let elevation = NORMALIZATION_ELEVATION.to_radians();
+        let reference_sun = Vec3::new(0.0, elevation.sin(), elevation.cos());
+        let zenith = Vec3::new(0.0, 1.0, 0.0);
+        let luminance = HosekWilkie::with_dataset_units(data, turbidity, albedo, reference_sun).get_sky_color(zenith).luminance();
+        let scale = if luminance > 0.0 { Preetham::new(turbidity, reference_sun).get_sky_color(zenith).luminance() / luminance } else { 1.0 };
+
+        HosekWilkie { radiances: model.radiances.map(|radiance| radiance * scale), ..model }
+    }
+
+    fn with_dataset_units(data: &HosekWilkieData, turbidity: f64, albedo: f64, sun_direction: Vec3) -> Self {
+        // The fit only covers the sun above the horizon.
+        let elevation = sun_direction.y.clamp(0.0, 1.0).asin();
+
+        let mut configurations = [[0.0; PARAMETERS]; 3];
+        let mut radiances = [0.0; 3];
+
+        for channel in 0..3 {
+            configurations[channel] = cook::<PARAMETERS>(&data.coefficients[channel], turbidity, albedo, elevation);
+            radiances[channel] = cook::<1>(&data.radiances[channel], turbidity, albedo, elevation)[0];
+        }
+
+        HosekWilkie { sun_direction, configurations, radiances }
+    }
+
+    /// Linear RGB sky radiance in the direction. Directions below the horizon mirror those above it.
+    pub fn get_sky_color(&self, direction: Vec3) -> Color {
+        let cos_theta = direction.y.abs().min(1.0);
+        let cos_gamma = direction.dot(self.sun_direction).clamp(-1.0, 1.0);
+        let gamma = cos_gamma.acos();
+
+        let channel = |i: usize| {
+            let c = &self.configurations[i];
+
+            let exponential = (c[4] * gamma).exp();
+            let rayleigh = cos_gamma * cos_gamma;
+            let mie = (1.0 + cos_gamma * cos_gamma) / (1.0 + c[8] * c[8] - 2.0 * c[8] * cos_gamma).powf(1.5);
+            let zenith = cos_theta.sqrt();
+
+            let radiance = (1.0 + c[0] * (c[1] / (cos_theta + 0.01)).exp()) * (c[2] + c[3] * exponential + c[5] * rayleigh + c[6] * mie + c[7] * zenith);
+
+            (radiance * self.radiances[i]).max(0.0)
+        };
+
+        Color::new(channel(0), channel(1), channel(2))
+    }
+}
+
+/// Interpolates `N` parameters from a dataset: a quintic Bezier curve over the cube root of the normalized sun
+/// elevation, then linearly between the neighbouring integer turbidities and between albedo 0 and 1.
+fn cook<const N: usize>(dataset: &[f64], turbidity: f64, albedo: f64, elevation: f64) -> [f64; N] {
+    const BINOMIALS: [f64; CONTROL_POINTS] = [1.0, 5.0, 10.0, 10.0, 5.0, 1.0];
+
+    let turbidity = turbidity.clamp(1.0, TURBIDITIES as f64);
+    let low = (turbidity as usize).min(TURBIDITIES - 1);
+    let remainder = turbidity - low as f64;
+    let s = (elevation / FRAC_PI_2).cbrt();
+
+    let block = N * CONTROL_POINTS;
+    let bezier = |albedo_index: usize, turbidity_index: usize, i: usize| {
+        let start = albedo_index * TURBIDITIES * block + (turbidity_index - 1) * block;
+
+        (0..CONTROL_POINTS)
+            .map(|k| BINOMIALS[k] * (1.0 - s).powi((CONTROL_POINTS - 1 - k) as i32) * s.powi(k as i32) * dataset[start + k * N + i])
+            .sum::<f64>()
+    };
+
+    let mut result = [0.0; N];
+    for (i, value) in result.iter_mut().enumerate() {
+        *value = (1.0 - albedo) * (1.0 - remainder) * bezier(0, low, i)
+            + albedo * (1.0 - remainder) * bezier(1, low, i)
+            + (1.0 - albedo) * remainder * bezier(0, low + 1, i)
+            + albedo * remainder * bezier(1, low + 1, i);
+    }
+
+    result
+}
+
+fn strip_comments(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('/') {
+        result.push_str(&rest[..start]);
+        let comment = &rest[start..];
+
+        rest = if comment.starts_with("//") {
+            comment.find('\n').map_or("", |end| &comment[end..])
+        } else if comment.starts_with("/*") {
+            comment.find("*/").map_or("", |end| &comment[end + 2..])
+        } else {
+            result.push('/');
+            &comment[1..]
+        };
+    }
+
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A dataset in the format of the reference header, with every value given by its channel and index.
+    fn header(coefficient: impl Fn(usize, usize) -> f64, radiance: impl Fn(usize, usize) -> f64) -> String {
+        let array = |length: usize, value: &dyn Fn(usize) -> f64| (0..length).map(|index| value(index).to_string()).collect::<Vec<_>>().join(", ");
+
+        (1..=3)
+            .map(|channel| format!(
+                "// Channel {}\ndouble datasetRGB{}[] = {{ {} }};\n/* radiance */ double datasetRGBRad{}[] = {{ {} }};\n",
+                channel,
+                channel,
+                array(ALBEDOS * TURBIDITIES * CONTROL_POINTS * PARAMETERS, &|index| coefficient(channel, index)),
+                channel,
+                array(ALBEDOS * TURBIDITIES * CONTROL_POINTS, &|index| radiance(channel, index)),
+            ))
+            .collect()
+    }
+
+    /// The same distribution parameters at every elevation, turbidity and albedo.
+    fn synthetic_header(radiance: f64) -> String {
+        const CONFIGURATION: [f64; PARAMETERS] = [-1.2, -0.3, -0.1, 1.5, -2.0, 0.2, 0.4, 1.0, 0.7];
+
+        header(|_, index| CONFIGURATION[index % PARAMETERS], |_, _| radiance)
+    }
+
+    /// `ArHosekSkyModel_CookConfiguration` and `ArHosekSkyModel_CookRadianceConfiguration` of the reference
+    /// implementation, transcribed line by line for `n` values per control point.
+    fn reference_cook(dataset: &[f64], n: usize, turbidity: f64, albedo: f64, solar_elevation: f64) -> Vec<f64> {
+        let int_turbidity = turbidity as usize;
+        let turbidity_rem = turbidity - int_turbidity as f64;
+        let solar_elevation = (solar_elevation / FRAC_PI_2).powf(1.0 / 3.0);
+
+        let curve = |offset: usize, i: usize| {
+            let elev_matrix = &dataset[offset..];
+
+            (1.0 - solar_elevation).powf(5.0) * elev_matrix[i]
+                + 5.0 * (1.0 - solar_elevation).powf(4.0) * solar_elevation * elev_matrix[i + n]
+                + 10.0 * (1.0 - solar_elevation).powf(3.0) * solar_elevation.powf(2.0) * elev_matrix[i + 2 * n]
+                + 10.0 * (1.0 - solar_elevation).powf(2.0) * solar_elevation.powf(3.0) * elev_matrix[i + 3 * n]
+                + 5.0 * (1.0 - solar_elevation) * solar_elevation.powf(4.0) * elev_matrix[i + 4 * n]
+                + solar_elevation.powf(5.0) * elev_matrix[i + 5 * n]
+        };
+
+        (0..n)
+            .map(|i| {
+                let mut value = (1.0 - albedo) * (1.0 - turbidity_rem) * curve(n * 6 * (int_turbidity - 1), i)
+                    + albedo * (1.0 - turbidity_rem) * curve(n * 6 * 10 + n * 6 * (int_turbidity - 1), i);
+                if int_turbidity < 10 {
+                    value += (1.0 - albedo) * turbidity_rem * curve(n * 6 * int_turbidity, i)
+                        + albedo * turbidity_rem * curve(n * 6 * 10 + n * 6 * int_turbidity, i);
+                }
+
+                value
+            })
+            .collect()
+    }
+
+    /// `ArHosekSkyModel_GetRadianceInternal` times the channel's radiance, as in `arhosek_tristim_skymodel_radiance`.
+    fn reference_radiance(configuration: &[f64], radiance: f64, theta: f64, gamma: f64) -> f64 {
+        let exp_m = (configuration[4] * gamma).exp();
+        let ray_m = gamma.cos() * gamma.cos();
+        let mie_m = (1.0 + gamma.cos() * gamma.cos()) / (1.0 + configuration[8] * configuration[8] - 2.0 * configuration[8] * gamma.cos()).powf(1.5);
+        let zenith = theta.cos().sqrt();
+
+        (1.0 + configuration[0] * (configuration[1] / (theta.cos() + 0.01)).exp())
+            * (configuration[2] + configuration[3] * exp_m + configuration[5] * ray_m + configuration[6] * mie_m + configuration[7] * zenith)
+            * radiance
+    }
+
+    /// Compares the model in dataset units with the reference implementation over the sky.
+    fn assert_matches_reference(data: &HosekWilkieData, turbidity: f64, albedo: f64, elevation_degrees: f64) {
+        let elevation = elevation_degrees.to_radians();
+        let sun_direction = Vec3::new(0.0, elevation.sin(), elevation.cos());
+        let model = HosekWilkie::with_dataset_units(data, turbidity, albedo, sun_direction);
+
+        for channel in 0..3 {
+            let configuration = reference_cook(&data.coefficients[channel], PARAMETERS, turbidity, albedo, elevation);
+            let radiance = reference_cook(&data.radiances[channel], 1, turbidity, albedo, elevation)[0];
+
+            for (a, b) in model.configurations[channel].iter().zip(&configuration) {
+                assert!((a - b).abs() <= 1e-9 * b.abs().max(1.0), "{} instead of {}", a, b);
+            }
+
+            for k in 0..50 {
+                let (theta, phi) = (k as f64 * 0.031, k as f64 * 2.4);
+                let direction = Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+                let gamma = direction.dot(sun_direction).clamp(-1.0, 1.0).acos();
+
+                let expected = reference_radiance(&configuration, radiance, theta, gamma).max(0.0);
+                let value = model.get_sky_color(direction).to_array()[channel];
+                assert!((value - expected).abs() <= 1e-9 * expected.abs().max(1.0), "{} instead of {} at theta {}, gamma {}", value, expected, theta, gamma);
+            }
+        }
+    }
+
+    #[test]
+    fn parses_reference_header_format() {
+        assert!(HosekWilkieData::parse(&synthetic_header(5.0)).is_ok());
+        assert_eq!(HosekWilkieData::parse("double datasetRGB1[] = { 1, 2 };").unwrap_err(), "array datasetRGB1 has 2 values, expected 1080");
+        assert_eq!(HosekWilkieData::parse("").unwrap_err(), "array datasetRGB1 not found");
+    }
+
+    #[test]
+    fn brightness_does_not_depend_on_dataset_units() {
+        let zenith = Vec3::new(0.0, 1.0, 0.0);
+        let elevation = NORMALIZATION_ELEVATION.to_radians();
+        let sun_direction = Vec3::new(0.0, elevation.sin(), elevation.cos());
+
+        for turbidity in [2.0, 6.5] {
+            let preetham = Preetham::new(turbidity, sun_direction).get_sky_color(zenith).luminance();
+            let low = HosekWilkie::new(&HosekWilkieData::parse(&synthetic_header(1.0)).unwrap(), turbidity, 0.3, sun_direction);
+            let high = HosekWilkie::new(&HosekWilkieData::parse(&synthetic_header(250.0)).unwrap(), turbidity, 0.3, sun_direction);
+
+            assert!((low.get_sky_color(zenith).luminance() / preetham - 1.0).abs() < 1e-9);
+            assert!((high.get_sky_color(zenith).luminance() / preetham - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn cooks_like_the_reference_implementation() {
+        // Every value differs, so reading the wrong control point, turbidity or albedo block shows.
+        let data = HosekWilkieData::parse(&header(
+            |channel, index| ((index * 7919 + channel * 104729) % 2003) as f64 / 2003.0 - 0.3,
+            |channel, index| 1.0 + ((index * 31 + channel * 17) % 101) as f64,
+        )).unwrap();
+
+        for (turbidity, albedo, elevation) in [(1.0, 0.0, 5.0), (2.0, 0.3, 35.0), (3.7, 0.1, 60.0), (9.5, 1.0, 89.0), (10.0, 0.5, 20.0)] {
+            assert_matches_reference(&data, turbidity, albedo, elevation);
+        }
+    }
+
+    /// Needs the published coefficients: run with `HOSEK_WILKIE_DATA` set to the path of *ArHosekSkyModelData_RGB.h*
+    /// and `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn matches_the_reference_implementation_with_published_data() {
+        let path = std::env::var("HOSEK_WILKIE_DATA").expect("HOSEK_WILKIE_DATA is not set");
+        let data = HosekWilkieData::from_file(Path::new(&path)).unwrap();
+
+        for (turbidity, albedo, elevation) in [(3.0, 0.1, 30.0), (6.5, 0.5, 10.0)] {
+            assert_matches_reference(&data, turbidity, albedo, elevation);
+        }
+
+        // A clear sky is brighter around the sun than opposite it, and brighter at the horizon than at the zenith
+        // away from the sun.
+        let elevation = 30f64.to_radians();
+        let model = HosekWilkie::with_dataset_units(&data, 3.0, 0.1, Vec3::new(0.0, elevation.sin(), elevation.cos()));
+        let near_sun = model.get_sky_color(Vec3::new(0.0, 0.6, 0.8).normalize()).luminance();
+        let opposite = model.get_sky_color(Vec3::new(0.0, 0.6, -0.8).normalize()).luminance();
+        let zenith = model.get_sky_color(Vec3::new(0.0, 1.0, 0.0)).luminance();
+        let horizon = model.get_sky_color(Vec3::new(0.0, 0.05, -1.0).normalize()).luminance();
+        assert!(near_sun > opposite && opposite > 0.0 && horizon > zenith, "{} {} {} {}", near_sun, opposite, zenith, horizon);
+    }
+}
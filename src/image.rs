@@ -0,0 +1,337 @@
+// Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
+
+use std::io::{self, Seek, Write};
+use exr::prelude::{f16, Compression, Encoding, Image, Layer, LayerAttributes, SpecificChannels, Vec2, WritableImage};
+use crate::color::Color;
+
+/// A rendered image with linear, unclamped colors.
+#[derive(Debug, Clone)]
+pub struct FaceImage {
+    pub name: String,
+    pub width: usize,
+    pub height: usize,
+    /// Row-major pixels relative to the sun brightness, so the sun disk is close to 1.0.
+    pub pixels: Vec<Color>,
+    /// Multiplier that turns `pixels` into radiance, i.e. the configured sun brightness.
+    pub brightness: f64,
+}
+
+/// Sample type of the EXR channels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExrPrecision {
+    Half,
+    Float,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExrCompression {
+    None,
+    Rle,
+    /// Deflate, one scan line per block.
+    Zips,
+    /// Deflate, 16 scan lines per block.
+    Zip,
+    /// Wavelet and Huffman, usually the smallest for noisy images.
+    Piz,
+}
+
+impl FaceImage {
+    pub fn new(name: &str, width: usize, height: usize, pixels: Vec<Color>, brightness: f64) -> Self {
+        FaceImage { name: name.to_string(), width, height, pixels, brightness }
+    }
+
+    /// Writes a 16-bit RGB PNG. Colors are scaled by the brightness and converted to sRGB, or written
+    /// linear and unscaled with `raw_color`. Both clamp to [0, 1].
+    pub fn write_png<W: Write>(&self, w: W, raw_color: bool) -> Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(w, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::RGB);
+        encoder.set_depth(png::BitDepth::Sixteen);
+        let mut writer = encoder.write_header()?;
+
+        let mut data = Vec::with_capacity(self.pixels.len() * 6);
+        for &color in self.pixels.iter() {
+            if raw_color {
+                data.extend_from_slice(&color.to_lin_48_u8());
+            } else {
+                data.extend_from_slice(&(color * self.brightness).to_srgb_48_u8());
+            }
+        }
+
+        writer.write_image_data(&data) // Save
+    }
+
+    /// Writes a Radiance RGBE (.hdr) image with the unclamped linear radiance. Scan lines are run-length encoded
+    /// whenever the width allows it.
+    pub fn write_hdr<W: Write>(&self, mut w: W) -> io::Result<()> {
+        write!(w, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", self.height, self.width)?;
+
+        let use_rle = (8..0x8000).contains(&self.width);
+        let mut components = vec![vec![0; self.width]; 4];
+
+        for row in self.pixels.chunks(self.width) {
+            let rgbe_row: Vec<[u8; 4]> = row.iter().map(|&color| (color * self.brightness).to_rgbe()).collect();
+
+            if !use_rle {
+                for rgbe in rgbe_row.iter() {
+                    w.write_all(rgbe)?;
+                }
+                continue;
+            }
+
+            w.write_all(&[2, 2, (self.width >> 8) as u8, self.width as u8])?;
+            for (i, component) in components.iter_mut().enumerate() {
+                for (value, rgbe) in component.iter_mut().zip(rgbe_row.iter()) {
+                    *value = rgbe[i];
+                }
+                write_rle_component(&mut w, component)?;
+            }
+        }
+
+        w.flush()
+    }
+
+    /// Writes an OpenEXR image with the unclamped linear radiance.
+    pub fn write_exr<W: Write + Seek>(&self, w: W, precision: ExrPrecision, compression: ExrCompression) -> exr::error::UnitResult {
+        let encoding = Encoding {
+            compression: match compression {
+                ExrCompression::None => Compression::Uncompressed,
+                ExrCompression::Rle => Compression::RLE,
+                ExrCompression::Zips => Compression::ZIP1,
+                ExrCompression::Zip => Compression::ZIP16,
+                ExrCompression::Piz => Compression::PIZ,
+            },
+            ..Encoding::default()
+        };
+
+        let radiance = |x: usize, y: usize| (self.pixels[y * self.width + x] * self.brightness).to_array();
+
+        match precision {
+            ExrPrecision::Half => {
+                let channels = SpecificChannels::rgb(|Vec2(x, y)| {
+                    let [r, g, b] = radiance(x, y);
+                    (f16::from_f64(r), f16::from_f64(g), f16::from_f64(b))
+                });
+
+                Image::from_layer(Layer::new((self.width, self.height), LayerAttributes::named(self.name.as_str()), encoding, channels))
+                    .write()
+                    .non_parallel()
+                    .to_buffered(w)
+            },
+            ExrPrecision::Float => {
+                let channels = SpecificChannels::rgb(|Vec2(x, y)| {
+                    let [r, g, b] = radiance(x, y);
+                    (r as f32, g as f32, b as f32)
+                });
+
+                Image::from_layer(Layer::new((self.width, self.height), LayerAttributes::named(self.name.as_str()), encoding, channels))
+                    .write()
+                    .non_parallel()
+                    .to_buffered(w)
+            },
+        }
+    }
+}
+
+/// Writes one component of a scan line with the Radiance run-length encoding: a count above 128 is a run of
+/// `count - 128` copies of the next byte, otherwise `count` literal bytes follow.
+fn write_rle_component<W: Write>(w: &mut W, data: &[u8]) -> io::Result<()> {
+    const MIN_RUN_LENGTH: usize = 4;
+
+    let n = data.len();
+    let mut cur = 0;
+
+    while cur < n {
+        // Find the next run that is long enough to be worth encoding.
+        let mut beg_run = cur;
+        let mut run_count = 0;
+        let mut old_run_count = 0;
+        while run_count < MIN_RUN_LENGTH && beg_run < n {
+            beg_run += run_count;
+            old_run_count = run_count;
+            run_count = 1;
+            while beg_run + run_count < n && run_count < 127 && data[beg_run] == data[beg_run + run_count] {
+                run_count += 1;
+            }
+        }
+
+        // A short run right before the long one is still cheaper as a run.
+        if old_run_count > 1 && old_run_count == beg_run - cur {
+            w.write_all(&[128 + old_run_count as u8, data[cur]])?;
+            cur = beg_run;
+        }
+
+        while cur < beg_run {
+            let literal_count = (beg_run - cur).min(128);
+            w.write_all(&[literal_count as u8])?;
+            w.write_all(&data[cur..cur + literal_count])?;
+            cur += literal_count;
+        }
+
+        if run_count >= MIN_RUN_LENGTH {
+            w.write_all(&[128 + run_count as u8, data[beg_run]])?;
+            cur += run_count;
+        }
+    }
+
+    Ok(())
+}
+
+impl ExrPrecision {
+    pub fn from_name(name: &str) -> Option<ExrPrecision> {
+        match name {
+            "half" => Some(ExrPrecision::Half),
+            "float" => Some(ExrPrecision::Float),
+            _ => None,
+        }
+    }
+}
+
+impl ExrCompression {
+    pub fn from_name(name: &str) -> Option<ExrCompression> {
+        match name {
+            "none" => Some(ExrCompression::None),
+            "rle" => Some(ExrCompression::Rle),
+            "zips" => Some(ExrCompression::Zips),
+            "zip" => Some(ExrCompression::Zip),
+            "piz" => Some(ExrCompression::Piz),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use exr::prelude::{read, ReadChannels, ReadLayers};
+    use super::*;
+
+    /// Pixels with long runs, short runs and noise, so that every branch of the run-length encoder is used.
+    fn test_image(width: usize, height: usize) -> FaceImage {
+        let pixels = (0..width * height)
+            .map(|index| {
+                let (x, y) = (index % width, index / width);
+                match y % 3 {
+                    0 => Color::new(0.25, 1.5, 3.0),
+                    1 if x % 200 < 150 => Color::new(0.01, 0.01, 0.01),
+                    1 => Color::new(x as f64 * 0.37, 0.0, 120.0),
+                    _ => Color::new(((x * 7919) % 257) as f64 / 64.0, (x % 5) as f64, 0.5 + (x / 4) as f64 * 1e-3),
+                }
+            })
+            .collect();
+
+        FaceImage::new("test", width, height, pixels, 2.0)
+    }
+
+    /// Decodes a Radiance file written by `write_hdr` into its RGBE pixels, and whether the scan lines were run-length encoded.
+    fn read_hdr(data: &[u8]) -> (usize, usize, Vec<[u8; 4]>, bool) {
+        let header_end = data.windows(2).position(|pair| pair == b"\n\n").unwrap() + 2;
+        let resolution_end = header_end + data[header_end..].iter().position(|&byte| byte == b'\n').unwrap();
+        let resolution = std::str::from_utf8(&data[header_end..resolution_end]).unwrap();
+        let parts: Vec<&str> = resolution.split(' ').collect();
+        let (height, width) = (parts[1].parse::<usize>().unwrap(), parts[3].parse::<usize>().unwrap());
+
+        let mut bytes = data[resolution_end + 1..].iter().copied();
+        let mut pixels = Vec::with_capacity(width * height);
+        let mut rle = false;
+
+        for _ in 0..height {
+            let start: Vec<u8> = bytes.clone().take(4).collect();
+            if start[0] != 2 || start[1] != 2 {
+                pixels.extend((0..width).map(|_| [(); 4].map(|_| bytes.next().unwrap())));
+                continue;
+            }
+
+            assert_eq!(((start[2] as usize) << 8) | start[3] as usize, width);
+            bytes.nth(3);
+            rle = true;
+
+            let mut row = vec![[0; 4]; width];
+            for component in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = bytes.next().unwrap() as usize;
+                    if count > 128 {
+                        let value = bytes.next().unwrap();
+                        for texel in &mut row[x..x + count - 128] {
+                            texel[component] = value;
+                        }
+                        x += count - 128;
+                    } else {
+                        assert!(count > 0);
+                        for texel in &mut row[x..x + count] {
+                            texel[component] = bytes.next().unwrap();
+                        }
+                        x += count;
+                    }
+                }
+                assert_eq!(x, width);
+            }
+            pixels.extend(row);
+        }
+        assert!(bytes.next().is_none());
+
+        (width, height, pixels, rle)
+    }
+
+    #[test]
+    fn hdr_round_trip() {
+        // Widths below 8 and above 32767 cannot be run-length encoded.
+        for (width, height) in [(1, 3), (7, 3), (8, 3), (300, 6), (32767, 3), (32768, 3)] {
+            let image = test_image(width, height);
+            let mut data = Vec::new();
+            image.write_hdr(&mut data).unwrap();
+
+            let (read_width, read_height, pixels, rle) = read_hdr(&data);
+            assert_eq!((read_width, read_height), (width, height));
+            assert_eq!(rle, (8..0x8000).contains(&width), "width {}", width);
+
+            for (rgbe, &color) in pixels.iter().zip(image.pixels.iter()) {
+                let radiance = (color * image.brightness).to_array();
+                assert_eq!(*rgbe, (color * image.brightness).to_rgbe());
+
+                // The mantissas are truncated to 8 bits of the largest component.
+                let scale = 2.0f64.powi(rgbe[3] as i32 - 136);
+                let largest = radiance.iter().fold(0.0f64, |a, &b| a.max(b));
+                for channel in 0..3 {
+                    let decoded = rgbe[channel] as f64 * scale;
+                    assert!((decoded - radiance[channel]).abs() <= largest / 128.0, "{} decoded as {}", radiance[channel], decoded);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn exr_round_trip() {
+        let image = test_image(37, 5);
+
+        for (precision, tolerance) in [(ExrPrecision::Half, 1e-3), (ExrPrecision::Float, 1e-7)] {
+            for compression in [ExrCompression::None, ExrCompression::Rle, ExrCompression::Zips, ExrCompression::Zip, ExrCompression::Piz] {
+                let mut data = Cursor::new(Vec::new());
+                image.write_exr(&mut data, precision, compression).unwrap();
+                data.set_position(0);
+
+                let read_image = read()
+                    .no_deep_data()
+                    .largest_resolution_level()
+                    .rgba_channels(
+                        |resolution, _| (resolution.width(), vec![[0.0f32; 3]; resolution.area()]),
+                        |(width, pixels): &mut (usize, Vec<[f32; 3]>), position, (r, g, b, _): (f32, f32, f32, f32)| pixels[position.y() * *width + position.x()] = [r, g, b],
+                    )
+                    .first_valid_layer()
+                    .all_attributes()
+                    .non_parallel()
+                    .from_buffered(data)
+                    .unwrap();
+
+                let layer = &read_image.layer_data;
+                assert_eq!((layer.size.width(), layer.size.height()), (image.width, image.height));
+
+                for (read_color, &color) in layer.channel_data.pixels.1.iter().zip(image.pixels.iter()) {
+                    for (&read_value, value) in read_color.iter().zip((color * image.brightness).to_array()) {
+                        assert!((read_value as f64 - value).abs() <= value.abs() * tolerance, "{:?} {:?}: {} read as {}", precision, compression, value, read_value);
+                    }
+                }
+            }
+        }
+    }
+}
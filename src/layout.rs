@@ -0,0 +1,312 @@
+// Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
+
+use crate::vec3::Vec3;
+use crate::color::Color;
+use crate::face::Face;
+use crate::image::FaceImage;
+
+/// How the six cube faces are written out.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CubemapLayout {
+    /// One image per face, named after the face.
+    Separate,
+    /// 4x3 cells: +Y above, -X +Z +X -Z across the middle, -Y below.
+    HorizontalCross,
+    /// 3x4 cells: like the horizontal cross with -Z moved below -Y and turned upside down.
+    VerticalCross,
+    /// 6x1 cells: +X -X +Y -Y +Z -Z.
+    Strip,
+    /// 3x2 cells: +X -X +Y on top, -Y +Z -Z below.
+    Grid,
+}
+
+/// Which world direction each cube map face shows and how it is oriented.
+/// All of them use the cube map orientation table from the OpenGL and Direct3D specifications
+/// and differ only in how the cube axes relate to north, east and up.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CubemapConvention {
+    /// Right-handed world looking down -Z: +X is east, +Y is up and -Z is north.
+    OpenGl,
+    /// Left-handed world: +X is east, +Y is up and +Z is north.
+    DirectX,
+    /// Same axes and face orientation as DirectX.
+    Unity,
+}
+
+/// A cube map face in API order.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Slot {
+    PosX,
+    NegX,
+    PosY,
+    NegY,
+    PosZ,
+    NegZ,
+}
+
+impl CubemapLayout {
+    pub fn from_name(name: &str) -> Option<CubemapLayout> {
+        match name {
+            "separate" => Some(CubemapLayout::Separate),
+            "horizontal_cross" => Some(CubemapLayout::HorizontalCross),
+            "vertical_cross" => Some(CubemapLayout::VerticalCross),
+            "strip" => Some(CubemapLayout::Strip),
+            "grid" => Some(CubemapLayout::Grid),
+            _ => None,
+        }
+    }
+
+    /// The layout size in face-sized cells.
+    fn size(self) -> (usize, usize) {
+        match self {
+            CubemapLayout::Separate => (1, 1),
+            CubemapLayout::HorizontalCross => (4, 3),
+            CubemapLayout::VerticalCross => (3, 4),
+            CubemapLayout::Strip => (6, 1),
+            CubemapLayout::Grid => (3, 2),
+        }
+    }
+
+    /// Cell column, cell row and whether the face is turned by 180 degrees.
+    fn cells(self) -> Vec<(Slot, usize, usize, bool)> {
+        match self {
+            CubemapLayout::Separate => Vec::new(),
+            CubemapLayout::HorizontalCross => vec![
+                (Slot::PosY, 1, 0, false),
+                (Slot::NegX, 0, 1, false),
+                (Slot::PosZ, 1, 1, false),
+                (Slot::PosX, 2, 1, false),
+                (Slot::NegZ, 3, 1, false),
+                (Slot::NegY, 1, 2, false),
+            ],
+            CubemapLayout::VerticalCross => vec![
+                (Slot::PosY, 1, 0, false),
+                (Slot::NegX, 0, 1, false),
+                (Slot::PosZ, 1, 1, false),
+                (Slot::PosX, 2, 1, false),
+                (Slot::NegY, 1, 2, false),
+                (Slot::NegZ, 1, 3, true),
+            ],
+            CubemapLayout::Strip => vec![
+                (Slot::PosX, 0, 0, false),
+                (Slot::NegX, 1, 0, false),
+                (Slot::PosY, 2, 0, false),
+                (Slot::NegY, 3, 0, false),
+                (Slot::PosZ, 4, 0, false),
+                (Slot::NegZ, 5, 0, false),
+            ],
+            CubemapLayout::Grid => vec![
+                (Slot::PosX, 0, 0, false),
+                (Slot::NegX, 1, 0, false),
+                (Slot::PosY, 2, 0, false),
+                (Slot::NegY, 0, 1, false),
+                (Slot::PosZ, 1, 1, false),
+                (Slot::NegZ, 2, 1, false),
+            ],
+        }
+    }
+
+    /// Combines the six rendered faces into a single image. `faces` must contain every face, all square and the same size.
+    pub fn assemble(self, faces: &[FaceImage], convention: CubemapConvention) -> FaceImage {
+        let n = faces[0].width;
+        let (columns, rows) = self.size();
+        let width = columns * n;
+        let mut pixels = vec![Color::new(0.0, 0.0, 0.0); width * rows * n];
+
+        for (slot, column, row, turned) in self.cells() {
+            let (forward, mut right, mut up) = slot.axes(convention);
+            if turned {
+                right = -right;
+                up = -up;
+            }
+
+            let face = Face::from_forward(forward);
+            let source = faces.iter().find(|image| image.name == face.name()).expect("a cube map layout needs all six faces");
+
+            for py in 0..n {
+                for px in 0..n {
+                    // Find the world direction of this pixel, then where that direction lies on the rendered face.
+                    let a = 2.0 * (px as f64 + 0.5) / n as f64 - 1.0;
+                    let b = 1.0 - 2.0 * (py as f64 + 0.5) / n as f64;
+                    let direction = forward + right * a + up * b;
+
+                    let sx = ((direction.dot(face.right()) + 1.0) * 0.5 * n as f64 - 0.5).round() as usize;
+                    let sy = ((1.0 - direction.dot(face.up())) * 0.5 * n as f64 - 0.5).round() as usize;
+
+                    pixels[(row * n + py) * width + column * n + px] = source.pixels[sy * n + sx];
+                }
+            }
+        }
+
+        FaceImage::new("cubemap", width, rows * n, pixels, faces[0].brightness)
+    }
+}
+
+impl CubemapConvention {
+    pub fn from_name(name: &str) -> Option<CubemapConvention> {
+        match name {
+            "opengl" => Some(CubemapConvention::OpenGl),
+            "directx" => Some(CubemapConvention::DirectX),
+            "unity" => Some(CubemapConvention::Unity),
+            _ => None,
+        }
+    }
+
+    /// The world direction of a cube axis.
+    fn to_world(self, x: f64, y: f64, z: f64) -> Vec3 {
+        match self {
+            CubemapConvention::OpenGl => Vec3::new(x, y, -z),
+            CubemapConvention::DirectX | CubemapConvention::Unity => Vec3::new(x, y, z),
+        }
+    }
+}
+
+impl Slot {
+    /// Forward, image right and image up directions of the face in world space.
+    fn axes(self, convention: CubemapConvention) -> (Vec3, Vec3, Vec3) {
+        let w = |x, y, z| convention.to_world(x, y, z);
+
+        match self {
+            Slot::PosX => (w(1.0, 0.0, 0.0), w(0.0, 0.0, -1.0), w(0.0, 1.0, 0.0)),
+            Slot::NegX => (w(-1.0, 0.0, 0.0), w(0.0, 0.0, 1.0), w(0.0, 1.0, 0.0)),
+            Slot::PosY => (w(0.0, 1.0, 0.0), w(1.0, 0.0, 0.0), w(0.0, 0.0, -1.0)),
+            Slot::NegY => (w(0.0, -1.0, 0.0), w(1.0, 0.0, 0.0), w(0.0, 0.0, 1.0)),
+            Slot::PosZ => (w(0.0, 0.0, 1.0), w(1.0, 0.0, 0.0), w(0.0, 1.0, 0.0)),
+            Slot::NegZ => (w(0.0, 0.0, -1.0), w(-1.0, 0.0, 0.0), w(0.0, 1.0, 0.0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const N: usize = 16;
+    const LAYOUTS: [CubemapLayout; 4] = [CubemapLayout::HorizontalCross, CubemapLayout::VerticalCross, CubemapLayout::Strip, CubemapLayout::Grid];
+    const CONVENTIONS: [CubemapConvention; 3] = [CubemapConvention::OpenGl, CubemapConvention::DirectX, CubemapConvention::Unity];
+
+    /// Rendered faces whose pixels hold the direction they were rendered in.
+    fn direction_coded_faces() -> Vec<FaceImage> {
+        Face::ALL
+            .iter()
+            .map(|&face| {
+                let camera = face.camera();
+                let pixels = (0..N * N)
+                    .map(|index| camera.get_ray(((index % N) as f64 + 0.5) / N as f64, ((index / N) as f64 + 0.5) / N as f64).direction)
+                    .map(|direction| Color::new(direction.x, direction.y, direction.z))
+                    .collect();
+
+                FaceImage::new(face.name(), N, N, pixels, 1.0)
+            })
+            .collect()
+    }
+
+    /// The world direction of pixel (x, y) of the cell in `column` and `row`.
+    fn direction(image: &FaceImage, column: usize, row: usize, x: usize, y: usize) -> Vec3 {
+        let [r, g, b] = image.pixels[(row * N + y) * image.width + column * N + x].to_array();
+
+        Vec3::new(r, g, b)
+    }
+
+    /// Cube map texture coordinates of a cube direction on a face, from the major axis table of the OpenGL
+    /// specification, with t running down the image.
+    fn texture_coordinates(slot: Slot, r: Vec3) -> (f64, f64) {
+        let (sc, tc, ma) = match slot {
+            Slot::PosX => (-r.z, -r.y, r.x),
+            Slot::NegX => (r.z, -r.y, -r.x),
+            Slot::PosY => (r.x, r.z, r.y),
+            Slot::NegY => (r.x, -r.z, -r.y),
+            Slot::PosZ => (r.x, -r.y, r.z),
+            Slot::NegZ => (-r.x, -r.y, -r.z),
+        };
+
+        ((sc / ma + 1.0) / 2.0, (tc / ma + 1.0) / 2.0)
+    }
+
+    fn to_cube(convention: CubemapConvention, direction: Vec3) -> Vec3 {
+        match convention {
+            CubemapConvention::OpenGl => Vec3::new(direction.x, direction.y, -direction.z),
+            CubemapConvention::DirectX | CubemapConvention::Unity => direction,
+        }
+    }
+
+    #[test]
+    fn faces_follow_the_specification() {
+        let faces = direction_coded_faces();
+        let expected_cells = |layout: CubemapLayout| match layout {
+            CubemapLayout::HorizontalCross => [(1, 0), (0, 1), (1, 1), (2, 1), (3, 1), (1, 2)],
+            CubemapLayout::VerticalCross => [(1, 0), (0, 1), (1, 1), (2, 1), (1, 3), (1, 2)],
+            CubemapLayout::Strip => [(2, 0), (1, 0), (4, 0), (0, 0), (5, 0), (3, 0)],
+            CubemapLayout::Grid => [(2, 0), (1, 0), (1, 1), (0, 0), (2, 1), (0, 1)],
+            CubemapLayout::Separate => unreachable!(),
+        };
+
+        for layout in LAYOUTS {
+            for convention in CONVENTIONS {
+                let image = layout.assemble(&faces, convention);
+
+                // +Y, -X, +Z, +X, -Z and -Y.
+                for (slot, (column, row)) in [Slot::PosY, Slot::NegX, Slot::PosZ, Slot::PosX, Slot::NegZ, Slot::NegY].into_iter().zip(expected_cells(layout)) {
+                    for (x, y) in [(0, 0), (N - 1, 0), (3, 9), (N - 1, N - 1)] {
+                        let (mut s, mut t) = texture_coordinates(slot, to_cube(convention, direction(&image, column, row, x, y)));
+                        // The vertical cross keeps -Z upside down.
+                        if layout == CubemapLayout::VerticalCross && slot == Slot::NegZ {
+                            (s, t) = (1.0 - s, 1.0 - t);
+                        }
+
+                        let (expected_s, expected_t) = ((x as f64 + 0.5) / N as f64, (y as f64 + 0.5) / N as f64);
+                        assert!((s - expected_s).abs() < 1e-9 && (t - expected_t).abs() < 1e-9, "{:?} {:?} {:?} pixel {:?}: {} {}", layout, convention, slot, (x, y), s, t);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cross_faces_meet_at_their_edges() {
+        let faces = direction_coded_faces();
+        // Neighbouring pixels across an edge are about a pixel apart.
+        let tolerance = (4.0 / N as f64).cos();
+
+        let crosses = [
+            (CubemapLayout::HorizontalCross, vec![((0, 1), (1, 1)), ((1, 1), (2, 1)), ((2, 1), (3, 1)), ((3, 1), (0, 1))], vec![((1, 0), (1, 1)), ((1, 1), (1, 2))]),
+            (CubemapLayout::VerticalCross, vec![((0, 1), (1, 1)), ((1, 1), (2, 1))], vec![((1, 0), (1, 1)), ((1, 1), (1, 2)), ((1, 2), (1, 3))]),
+        ];
+
+        for (layout, side_by_side, stacked) in crosses {
+            for convention in CONVENTIONS {
+                let image = layout.assemble(&faces, convention);
+
+                for k in 0..N {
+                    for &((left_column, left_row), (right_column, right_row)) in &side_by_side {
+                        let (a, b) = (direction(&image, left_column, left_row, N - 1, k), direction(&image, right_column, right_row, 0, k));
+                        assert!(a.normalize().dot(b.normalize()) > tolerance, "{:?} {:?}: {:?} and {:?}", layout, convention, a, b);
+                    }
+                    for &((top_column, top_row), (bottom_column, bottom_row)) in &stacked {
+                        let (a, b) = (direction(&image, top_column, top_row, k, N - 1), direction(&image, bottom_column, bottom_row, k, 0));
+                        assert!(a.normalize().dot(b.normalize()) > tolerance, "{:?} {:?}: {:?} and {:?}", layout, convention, a, b);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn horizontal_cross_shows_the_world_directions() {
+        let image = CubemapLayout::HorizontalCross.assemble(&direction_coded_faces(), CubemapConvention::DirectX);
+        let centre = |column: usize, row: usize| {
+            let d = direction(&image, column, row, N / 2, N / 2) + direction(&image, column, row, N / 2 - 1, N / 2 - 1);
+            d.normalize()
+        };
+
+        // Up above north, with west, north, east and south across the middle and down below.
+        let expected = [((1, 0), Vec3::new(0.0, 1.0, 0.0)), ((0, 1), Vec3::new(-1.0, 0.0, 0.0)), ((1, 1), Vec3::new(0.0, 0.0, 1.0)), ((2, 1), Vec3::new(1.0, 0.0, 0.0)), ((3, 1), Vec3::new(0.0, 0.0, -1.0)), ((1, 2), Vec3::new(0.0, -1.0, 0.0))];
+        for ((column, row), direction) in expected {
+            assert!(centre(column, row).dot(direction) > 0.999, "cell {:?} shows {:?}", (column, row), centre(column, row));
+        }
+
+        // The top of the north cell looks up, and its right side east.
+        assert!(direction(&image, 1, 1, N / 2, 0).y > 0.5 * direction(&image, 1, 1, N / 2, 0).mag());
+        assert!(direction(&image, 1, 1, N - 1, N / 2).x > 0.5 * direction(&image, 1, 1, N - 1, N / 2).mag());
+    }
+}
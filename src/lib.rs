@@ -0,0 +1,44 @@
+// Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
+
+pub mod vec3;
+pub mod ray3;
+pub mod color;
+pub mod camera;
+pub mod cloud;
+pub mod height_profile;
+pub mod coverage;
+pub mod background;
+pub mod sun;
+pub mod night;
+pub mod atmosphere;
+pub mod preetham;
+pub mod hosek_wilkie;
+pub mod phase;
+mod fast_rng;
+pub mod renderer;
+pub mod scheduler;
+pub mod progress;
+pub mod face;
+pub mod view;
+pub mod layout;
+pub mod image;
+pub mod error;
+pub mod config;
+pub mod skybox;
+pub mod output;
+
+pub use color::Color;
+pub use face::Face;
+pub use background::SkyModel;
+pub use atmosphere::AtmosphereParameters;
+pub use phase::PhaseFunction;
+pub use height_profile::HeightProfile;
+pub use cloud::{NoiseType, WarpType};
+pub use view::{Projection, View};
+pub use layout::{CubemapConvention, CubemapLayout};
+pub use image::{ExrCompression, ExrPrecision, FaceImage};
+pub use error::{ConfigIssue, SkyboxError};
+pub use config::SkyboxConfig;
+pub use skybox::Skybox;
+pub use progress::{CancellationToken, Progress, ViewProgress};
+pub use output::{ImageFormat, OutputTemplate, OverwritePolicy};
@@ -1,251 +1,330 @@
 // Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
 
-mod vec3;
-mod ray3;
-mod color;
-mod camera;
-use camera::Camera;
-mod cloud;
-use cloud::Cloud;
-mod background;
-mod fast_rng;
-mod renderer;
-
-use std::{fs, path};
-use std::fs::OpenOptions;
-use std::io::BufWriter;
-#[cfg(not(feature = "no-multithreading"))]
-use std::sync::mpsc;
-#[cfg(not(feature = "no-multithreading"))]
-use std::thread;
-use std::time::Instant;
+mod cli;
+use cli::Args;
+
+use std::{fs, io, process};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use configparser::ini::Ini;
-use rand::{RngCore, SeedableRng};
-use rand_xoshiro::Xoshiro256PlusPlus;
-use vec3::Vec3;
-use background::Background;
-use color::Color;
-use renderer::Renderer;
-
-#[cfg(not(feature = "no-multithreading"))]
-#[inline]
-fn compute_multithreaded(camera: &Camera, renderer: &Renderer, renderer_rng: &mut impl rand::Rng, img_data: &mut Vec<u8>, slice_count: usize, slice_height: usize, slice_length: usize) {
-    let (tx, rx) = mpsc::channel();
-
-    let mut transmitters = Vec::new();
-    for _i in 0..slice_count - 1 {
-        transmitters.push(mpsc::Sender::clone(&tx));
+use skybox::{CancellationToken, CubemapLayout, OverwritePolicy, Progress, Projection, Skybox, SkyboxConfig, SkyboxError, View};
+use skybox::config::{apply_override, read_ini};
+use skybox::output::save_image;
+
+fn main() {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("ERROR: {}\n\n{}", message, cli::USAGE);
+            process::exit(2);
+        },
+    };
+
+    if args.help {
+        println!("{}", cli::USAGE);
+        return;
     }
-    transmitters.push(tx);
 
-    for (i, transmitter) in transmitters.into_iter().enumerate() {
-        let frng_seed = renderer_rng.next_u64();
-        let min_py = i * slice_height;
+    let result = if args.validate_only {
+        validate(&args)
+    } else {
+        run(&args)
+    };
 
-        let camera = camera.clone();
+    if let Err(error) = result {
+        eprintln!("ERROR: {}", error);
+        process::exit(1);
+    }
+}
 
-        let renderer = renderer.clone();
+fn load_ini(args: &Args) -> Result<Ini, SkyboxError> {
+    let mut ini = read_ini(&args.config_path)?;
+    for assignment in args.overrides.iter() {
+        apply_override(&mut ini, assignment)?;
+    }
 
-        thread::spawn(move || {
-            let val = (i, renderer.render_slice(&camera, min_py, frng_seed));
+    Ok(ini)
+}
+
+/// Checks the configuration without rendering anything.
+fn validate(args: &Args) -> Result<(), SkyboxError> {
+    SkyboxConfig::from_ini(&load_ini(args)?)?.validate()?;
 
-            transmitter.send(val).unwrap();
-        });
+    println!("Configuration is valid.");
+
+    Ok(())
+}
+
+/// Expands the output template for one image. Besides {face}, {ext}, {name} and {frame}, any configuration key
+/// can be used either as {section.key} or, when only one section has the key, as {key}.
+fn output_path(args: &Args, ini: &Ini, config: &SkyboxConfig, image_name: &str, frame: Option<usize>) -> Result<PathBuf, SkyboxError> {
+    let name = args.config_path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+
+    let file_name = args.template.expand(|placeholder| match placeholder {
+        "face" => Ok(Some(image_name.to_string())),
+        "ext" => Ok(Some(config.images.format.extension().to_string())),
+        "name" => Ok(Some(name.clone())),
+        "frame" => Ok(frame.map(|frame| format!("{:04}", frame))),
+        _ => match placeholder.rsplit_once('.') {
+            Some((section, key)) => Ok(ini.get(section, key)),
+            None => {
+                let mut sections: Vec<String> = ini.sections().into_iter().filter(|section| ini.get(section, placeholder).is_some()).collect();
+                sections.sort();
+
+                match &sections[..] {
+                    [] => Ok(None),
+                    [section] => Ok(ini.get(section, placeholder)),
+                    _ => Err(format!(
+                        "\"{{{}}}\" is in sections {}, use {{section.{}}} to pick one",
+                        placeholder,
+                        sections.iter().map(|section| format!("[{}]", section)).collect::<Vec<_>>().join(", "),
+                        placeholder,
+                    )),
+                }
+            },
+        },
+    })?;
+
+    Ok(args.output_dir.join(file_name))
+}
+
+/// The image name of an output file and the views that are rendered into it.
+type Group<'a> = (&'a str, Vec<View>);
+
+/// Expands the output path of every group, failing if two groups would be saved to the same file.
+fn output_paths<'a>(args: &Args, ini: &Ini, config: &SkyboxConfig, groups: Vec<Group<'a>>, frame: Option<usize>) -> Result<Vec<(Group<'a>, PathBuf)>, SkyboxError> {
+    let mut names: HashMap<PathBuf, &str> = HashMap::new();
+    let mut outputs = Vec::new();
+
+    for (name, views) in groups {
+        let path = output_path(args, ini, config, name, frame)?;
+
+        if let Some(other) = names.insert(path.clone(), name) {
+            return Err(SkyboxError::InvalidTemplate {
+                template: args.template.as_str().to_string(),
+                reason: format!("the \"{}\" and \"{}\" images would both be saved to {}, add {{face}} to tell them apart", other, name, path.display()),
+            });
+        }
+        outputs.push(((name, views), path));
     }
 
-    for received in rx {
-        let (thread_index, slice) = received;
+    Ok(outputs)
+}
+
+/// A single-line progress bar with the estimated time left and the status of each image.
+fn progress_line(progress: &Progress) -> String {
+    const BAR_WIDTH: usize = 30;
+
+    let filled = (progress.fraction() * BAR_WIDTH as f64) as usize;
+    let eta = match progress.eta() {
+        Some(eta) => format!("{}:{:02}", eta.as_secs() / 60, eta.as_secs() % 60),
+        None => "-:--".to_string(),
+    };
+    let views: Vec<String> = progress.views.iter().map(|view| format!("{} {:.0}%", view.name, view.fraction() * 100.0)).collect();
+
+    format!("[{}{}] {:5.1}% ETA {} | {}", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled), progress.fraction() * 100.0, eta, views.join(", "))
+}
+
+fn run(args: &Args) -> Result<(), SkyboxError> {
+    let mut ini = load_ini(args)?;
+    let cancel = cancel_on_interrupt();
 
-        let i1 = thread_index * slice_length;
-        let i2 = i1 + slice_length;
+    let frames = match args.frames {
+        Some(frames) => frames,
+        None => return render_frame(args, &ini, None, &cancel),
+    };
 
-        println!("\tSlice #{} complete", thread_index);
+    // Evenly spaced from the first time to the last, or one unit of time apart from the configured time.
+    let (start, step) = match args.time_range {
+        Some((start, end)) => (start, if frames > 1 { (end - start) / (frames - 1) as f64 } else { 0.0 }),
+        None => (SkyboxConfig::from_ini(&ini)?.clouds.time, 1.0),
+    };
 
-        img_data[i1..i2].copy_from_slice(&slice);
+    for frame in 0..frames {
+        let time = start + step * frame as f64;
+        // Set in the INI rather than the config so that {clouds.time} in the template gives the frame's time.
+        ini.set("clouds", "time", Some(time.to_string()));
+
+        println!("Frame {} of {}, time {}", frame + 1, frames, time);
+        render_frame(args, &ini, Some(frame), &cancel)?;
     }
+
+    Ok(())
 }
 
-#[cfg(feature = "no-multithreading")]
-#[inline]
-fn compute_multithreaded(_camera: &Camera, _renderer: &Renderer, _renderer_rng: &mut impl rand::Rng, _img_data: &mut Vec<u8>, _slice_count: usize, _slice_height: usize, _slice_length: usize) {}
+/// A token that Ctrl-C cancels, so that the render stops between tiles and no unfinished image is saved.
+fn cancel_on_interrupt() -> CancellationToken {
+    let cancel = CancellationToken::new();
 
-fn main() {
-    // ---------- Set configuration variables ----------
+    #[cfg(not(target_os = "wasi"))]
+    {
+        let token = cancel.clone();
+        if let Err(error) = ctrlc::set_handler(move || token.cancel()) {
+            println!("WARNING: Ctrl-C will stop the program without cleaning up: {}", error);
+        }
+    }
+
+    cancel
+}
 
-    let config_str = fs::read_to_string("config.ini").expect("Error while reading the configuration file.");
-    let mut config = Ini::new();
-    let _ = config.read(config_str);
+/// Renders and saves every image of one frame, or of the only frame when `frame` is `None`.
+fn render_frame(args: &Args, ini: &Ini, frame: Option<usize>, cancel: &CancellationToken) -> Result<(), SkyboxError> {
+    // ---------- Set configuration variables ----------
 
-    let image_width = config.getuint("images", "image_width").unwrap().unwrap() as usize;
-    let image_height = config.getuint("images", "image_height").unwrap().unwrap() as usize;
-    let color_byte_size = 6;
-    let render_north_only = config.getbool("images", "render_north_only").unwrap().unwrap();
-    let raw_color = config.getbool("images", "raw_color").unwrap().unwrap();
+    let mut config = SkyboxConfig::from_ini(ini)?;
 
-    let mut use_multithreading = config.getbool("slices", "use_multithreading").unwrap().unwrap();
-    if cfg!(feature = "no-multithreading") && use_multithreading {
-        println!("WARNING: \"use_multithreading\" flag is set to true in the configuration file but multithreading is not supported in this build. Ignoring the flag.");
-        use_multithreading = false;
+    if cfg!(feature = "no-multithreading") && config.slices.use_multithreading {
+        if frame.unwrap_or(0) == 0 {
+            println!("WARNING: \"use_multithreading\" flag is set to true in the configuration file but multithreading is not supported in this build. Ignoring the flag.");
+        }
+        config.slices.use_multithreading = false;
     }
-    let slice_count = config.getuint("slices", "slice_count").unwrap().unwrap() as usize;
-    assert!(!use_multithreading || image_height % slice_count == 0);
-    let slice_height = image_height / slice_count;
-    let slice_length = image_width * slice_height * color_byte_size;
-
-    let min_height = config.getfloat("clouds", "min_height").unwrap().unwrap().into();
-    let max_height = config.getfloat("clouds", "max_height").unwrap().unwrap().into();
-    let cloud_threshold = config.getfloat("clouds", "cloud_threshold").unwrap().unwrap().into();
-    let noise_levels = config.getuint("clouds", "noise_levels").unwrap().unwrap() as u32;
-    let noise_scale = config.getfloat("clouds", "noise_scale").unwrap().unwrap() as f64;
-    let cloud_seed = config.getuint("clouds", "cloud_seed").unwrap().unwrap();
-    let min_fog_dist = config.getfloat("clouds", "min_fog_dist").unwrap().unwrap().into();
-    let max_fog_dist = config.getfloat("clouds", "max_fog_dist").unwrap().unwrap().into();
-    let step_size = config.getfloat("clouds", "step_size").unwrap().unwrap().into();
-    let step_count  = config.getuint("clouds", "step_count").unwrap().unwrap() as usize;
-
-    let sun_brightness = config.getfloat("background", "sun_brightness").unwrap().unwrap() as f64;
-    let sun_size = config.getfloat("background", "sun_size").unwrap().unwrap().into();
-    let sun_angle_phi = config.getfloat("background", "sun_angle_phi").unwrap().unwrap().into();
-    let sun_angle_theta = config.getfloat("background", "sun_angle_theta").unwrap().unwrap().into();
-    let sun_color = Color::from_str(&config.get("background", "sun_color").unwrap());
-    let sky_colors: Vec<Color> = config.get("background", "sky_colors").unwrap().split(",").map(|str| (Color::from_str(str.trim()) * (1.0 / sun_brightness))).collect();
-    let ground_color = Color::from_str(&config.get("background", "ground_color").unwrap()) * (1.0 / sun_brightness);
-
-    let renderer_seed = config.getuint("quality", "renderer_seed").unwrap().unwrap();
-    let pixel_width = config.getuint("quality", "pixel_width").unwrap().unwrap() as usize;
-    let bundle_size = config.getuint("quality", "bundle_size").unwrap().unwrap() as usize;
 
     // ---------- Initialize ----------
 
-    let mut scenes = vec![
-        (
-            "north",
-            Camera::new(
-                Vec3::new(0.0, 0.0, 1.0),
-                Vec3::new(0.0, 1.0, 0.0),
-            )
-        ),
-        (
-            "south",
-            Camera::new(
-                Vec3::new(0.0, 0.0, -1.0),
-                Vec3::new(0.0, 1.0, 0.0),
-            )
-        ),
-        (
-            "east",
-            Camera::new(
-                Vec3::new(1.0, 0.0, 0.0),
-                Vec3::new(0.0, 1.0, 0.0),
-            )
-        ),
-        (
-            "west",
-            Camera::new(
-                Vec3::new(-1.0, 0.0, 0.0),
-                Vec3::new(0.0, 1.0, 0.0),
-            )
-        ),
-        (
-            "up",
-            Camera::new(
-                Vec3::new(0.0, 1.0, 0.0),
-                Vec3::new(0.0, 0.0, 1.0),
-            )
-        ),
-        (
-            "down",
-            Camera::new(
-                Vec3::new(0.0, -1.0, 0.0),
-                Vec3::new(0.0, 0.0, 1.0),
-            )
-        ),
-    ];
-
-    if render_north_only {
-        scenes.truncate(1);
+    let skybox = Skybox::new(&config)?;
+
+    if args.faces.is_some() && frame.unwrap_or(0) == 0 && (config.images.projection == Projection::Equirectangular || skybox.layout() != CubemapLayout::Separate) {
+        println!("WARNING: --faces has no effect when rendering a single panorama or cube map image. Ignoring the option.");
     }
 
-    let mut clouds_rng = Xoshiro256PlusPlus::seed_from_u64(cloud_seed);
-    let cloud = Cloud::new(min_height, max_height, cloud_threshold, noise_levels, clouds_rng.next_u32() as i32, 256.0 / 2.0f64.powi(noise_levels as i32) * noise_scale);
-
-    let background = Background::new(sun_size, sun_color, sky_colors.clone(), ground_color, sun_angle_phi, sun_angle_theta);
-
-    let mut renderer_rng = Xoshiro256PlusPlus::seed_from_u64(renderer_seed);
-
-    let renderer = Renderer::new(
-        cloud,
-        slice_length,
-        color_byte_size,
-        image_width,
-        image_height,
-        min_fog_dist,
-        max_fog_dist,
-        step_size,
-        step_count,
-        pixel_width,
-        bundle_size,
-        background,
-        raw_color,
-        sun_brightness
-    );
+    // Every output file and the views that are rendered into it.
+    let groups: Vec<Group> = match (skybox.layout(), &args.faces) {
+        (CubemapLayout::Separate, Some(faces)) if config.images.projection == Projection::Cubemap => {
+            faces.iter().map(|&face| (face.name(), vec![View::Face(face)])).collect()
+        },
+        (CubemapLayout::Separate, _) => skybox.views().iter().map(|&view| (view.name(), vec![view])).collect(),
+        _ => vec![("cubemap", skybox.views().to_vec())],
+    };
+
+    // Resolve every output path before rendering so that a conflict is reported immediately rather than after a long render.
+    let mut outputs = Vec::new();
+    for ((name, views), path) in output_paths(args, ini, &config, groups, frame)? {
+        if path.exists() {
+            match args.overwrite_policy {
+                OverwritePolicy::Refuse => return Err(SkyboxError::OutputExists { path }),
+                OverwritePolicy::Skip => {
+                    println!("Skipping \"{}\" image, {} already exists.", name, path.display());
+                    continue;
+                },
+                OverwritePolicy::Overwrite => {},
+            }
+        }
+
+        outputs.push((views, path));
+    }
 
     // ---------- Create images ----------
 
     let outer_now = Instant::now();
 
-    for (name, camera) in scenes.iter() {
-        println!("Computing \"{}\" face...", name);
+    // ---------- Compute image data ----------
 
-        // ---------- Compute image data ----------
-
-        let mut img_data = vec![0; image_width * image_height * color_byte_size];
-        if use_multithreading {
-            compute_multithreaded(camera, &renderer, &mut renderer_rng, &mut img_data, slice_count, slice_height, slice_length);
-        } else {
-            for thread_index in 0..slice_count {
-                let frng_seed = renderer_rng.next_u64();
-                let min_py = thread_index * slice_height;
+    let views: Vec<View> = outputs.iter().flat_map(|(views, _)| views.iter().copied()).collect();
 
-                let val = (thread_index, renderer.render_slice(&camera, min_py, frng_seed));
+    println!("Computing {} image(s)...", views.len());
 
-                let (thread_index, slice) = val;
-
-                let i1 = thread_index * slice_length;
-                let i2 = i1 + slice_length;
-
-                println!("\tSlice #{} complete", thread_index);
+    let mut last_draw: Option<Instant> = None;
+    let draw_progress = |progress: &Progress| {
+        // Redraw at most ten times per second, but always show the final state.
+        let done = progress.completed_tiles == progress.total_tiles;
+        if !done && last_draw.is_some_and(|time| time.elapsed() < Duration::from_millis(100)) {
+            return;
+        }
+        last_draw = Some(Instant::now());
 
-                img_data[i1..i2].copy_from_slice(&slice);
-            }
+        // Clear to the end of the line, in case the previous line was longer.
+        print!("\r{}\x1b[K", progress_line(progress));
+        if done {
+            println!();
         }
+        let _ = io::stdout().flush();
+    };
 
-        // ---------- Write image file ----------
+    let images = skybox.render_views_with_progress(&views, draw_progress, cancel);
+    if images.is_err() {
+        // End the unfinished progress line.
+        println!();
+    }
+    let mut images = images?.into_iter();
 
-        let path_string = format!("output/{}.png", name);
-        let path = path::Path::new(&path_string);
-        let prefix = path.parent().unwrap();
-        std::fs::create_dir_all(prefix).unwrap();
-        let file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(path)
-            .unwrap();
-        let ref mut w = BufWriter::new(file);
+    for (views, path) in outputs {
+        let mut group: Vec<_> = images.by_ref().take(views.len()).collect();
 
-        let mut encoder = png::Encoder::new(w, image_width as u32, image_height as u32);
-        encoder.set_color(png::ColorType::RGB);
-        encoder.set_depth(png::BitDepth::Sixteen);
-        let mut writer = encoder.write_header().unwrap();
+        let image = if group.len() == 1 {
+            group.remove(0)
+        } else {
+            skybox.assemble(&group)
+        };
 
-        writer.write_image_data(&img_data).unwrap(); // Save
+        // ---------- Write image file ----------
 
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|source| SkyboxError::Io { path: parent.to_path_buf(), source })?;
+        }
+        save_image(&image, &path, &config.images, args.overwrite_policy)?;
 
-        println!("\"{}\" face complete.\n", name);
+        println!("Saved {}", path.display());
     }
 
     // ---------- Ending tasks ----------
 
     let elapsed = outer_now.elapsed();
     println!("Duration: {:.2?}", elapsed);
-}
\ No newline at end of file
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use skybox::Face;
+    use skybox::config::parse_ini;
+
+    fn path(template: &str, ini: &str) -> Result<PathBuf, SkyboxError> {
+        let args = Args::parse(["-c", "sky.ini", "-o", "out", "-t", template].iter().map(|arg| arg.to_string())).unwrap();
+        let ini = parse_ini(ini).unwrap();
+
+        output_path(&args, &ini, &SkyboxConfig::default(), "north", None)
+    }
+
+    #[test]
+    fn resolves_configuration_keys() {
+        let ini = "[clouds]\ncloud_seed = 7\ntime = 2\n[sun]\ntime = 12:00\n[clouds.cirrus]\ncloud_seed = 9\n";
+
+        assert_eq!(path("{name}_{face}.{ext}", ini).unwrap(), PathBuf::from("out/sky_north.png"));
+        assert_eq!(path("{clouds.cloud_seed}_{clouds.cirrus.cloud_seed}_{sun.time}", ini).unwrap(), PathBuf::from("out/7_9_12:00"));
+        assert_eq!(path("{clouds.time}", ini).unwrap(), PathBuf::from("out/2"));
+    }
+
+    #[test]
+    fn rejects_keys_in_several_sections() {
+        let ini = "[clouds]\ncloud_seed = 7\ntime = 2\n[sun]\ntime = 12:00\n[clouds.cirrus]\ncloud_seed = 9\n";
+
+        for template in ["{time}", "{cloud_seed}_{face}"] {
+            match path(template, ini) {
+                Err(SkyboxError::InvalidTemplate { reason, .. }) => assert!(reason.contains("{section."), "{}", reason),
+                other => panic!("{} expanded to {:?}", template, other),
+            }
+        }
+        assert!(matches!(path("{frame}", ini), Err(SkyboxError::InvalidTemplate { .. })));
+    }
+
+    #[test]
+    fn rejects_images_saved_to_the_same_file() {
+        let groups = || Face::ALL.iter().map(|&face| (face.name(), vec![View::Face(face)])).collect::<Vec<_>>();
+        let outputs = |template: &str| {
+            let args = Args::parse(["-o", "out", "-t", template].iter().map(|arg| arg.to_string())).unwrap();
+
+            output_paths(&args, &Ini::new(), &SkyboxConfig::default(), groups(), None)
+        };
+
+        assert_eq!(outputs("sky_{face}.{ext}").unwrap().len(), 6);
+        match outputs("sky.{ext}") {
+            Err(SkyboxError::InvalidTemplate { reason, .. }) => assert!(reason.contains("{face}"), "{}", reason),
+            other => panic!("expanded to {:?}", other),
+        }
+    }
+}
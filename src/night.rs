@@ -0,0 +1,329 @@
+// Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
+
+use std::f64::consts::PI;
+use std::fs;
+use std::path::Path;
+use crate::vec3::Vec3;
+use crate::color::Color;
+use crate::error::SkyboxError;
+use crate::fast_rng::Frng;
+use crate::sun::SunLocation;
+
+/// Faintest magnitude of procedural stars, about the naked eye limit.
+const FAINTEST_MAGNITUDE: f64 = 6.5;
+const BRIGHTEST_MAGNITUDE: f64 = -1.5;
+
+/// Cells along each side of a cube face in the star lookup grid.
+const GRID_SIZE: usize = 64;
+
+/// Spacing in radians of the points a star disk is sampled at to find its grid cells, a third of the smallest
+/// cell, which is at the corners of a face.
+const CELL_SAMPLE_SPACING: f64 = 0.005;
+
+// The galactic north pole and center in right ascension and declination, in degrees.
+const GALACTIC_POLE: (f64, f64) = (192.85948, 27.12825);
+const GALACTIC_CENTER: (f64, f64) = (266.40510, -28.93617);
+
+/// A star in equatorial coordinates: right ascension and declination in degrees, visual magnitude and B-V color index.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CatalogStar {
+    pub right_ascension: f64,
+    pub declination: f64,
+    pub magnitude: f64,
+    pub color_index: f64,
+}
+
+impl CatalogStar {
+    /// Reads a star catalog with one `right_ascension, declination, magnitude[, b-v]` line per star, the angles in degrees.
+    /// Blank lines and lines starting with `#` are skipped.
+    pub fn read_catalog(path: &Path) -> Result<Vec<CatalogStar>, SkyboxError> {
+        let text = fs::read_to_string(path).map_err(|source| SkyboxError::Io { path: path.to_path_buf(), source })?;
+
+        text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(|(index, line)| {
+                let invalid = || SkyboxError::InvalidData { path: path.to_path_buf(), reason: format!("line {}: expected right_ascension, declination, magnitude[, b-v]", index + 1) };
+
+                let values = line.split(',').map(|value| value.trim().parse::<f64>()).collect::<Result<Vec<f64>, _>>().map_err(|_| invalid())?;
+                match values[..] {
+                    [right_ascension, declination, magnitude] => Ok(CatalogStar { right_ascension, declination, magnitude, color_index: 0.6 }),
+                    [right_ascension, declination, magnitude, color_index] => Ok(CatalogStar { right_ascension, declination, magnitude, color_index }),
+                    _ => Err(invalid()),
+                }
+            })
+            .collect()
+    }
+
+    /// Random stars spread evenly over the celestial sphere, with about as many faint ones per bright one as the real sky.
+    pub fn generate(count: usize, seed: u64) -> Vec<CatalogStar> {
+        (0..count)
+            .map(|index| {
+                let mut frng = Frng::from_key(&[seed, index as u64]);
+
+                // Star counts grow roughly by a factor of 10^0.5 per magnitude.
+                let magnitude = (FAINTEST_MAGNITUDE + 2.0 * frng.next_double(1e-4, 1.0).log10()).max(BRIGHTEST_MAGNITUDE);
+
+                CatalogStar {
+                    right_ascension: frng.next_double(0.0, 360.0),
+                    declination: frng.next_double(-1.0, 1.0).asin().to_degrees(),
+                    magnitude,
+                    color_index: frng.next_double(-0.2, 1.6),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Everything needed to build a `NightSky`, in output brightness units.
+#[derive(Debug, Clone)]
+pub struct NightParameters {
+    /// Sky color once the sun is well below the horizon.
+    pub sky_color: Color,
+    pub moon_size: f64,
+    pub moon_color: Color,
+    pub moon_brightness: f64,
+    /// Angular radius of a star in radians.
+    pub star_size: f64,
+    /// Brightness of a magnitude 0 star. Every magnitude fainter divides it by about 2.5.
+    pub star_brightness: f64,
+    /// Brightness of the Milky Way along its center line, 0 to leave it out.
+    pub milky_way_brightness: f64,
+}
+
+#[derive(Debug, Clone)]
+struct Star {
+    direction: Vec3,
+    color: Color,
+}
+
+/// The moon, stars and Milky Way, positioned for a place and time on Earth. Colors are relative to the sun
+/// brightness like the rest of the background.
+#[derive(Debug, Clone)]
+pub struct NightSky {
+    sky_color: Color,
+    moon_direction: Vec3,
+    moon_size: f64,
+    moon_color: Color,
+    star_cos_size: f64,
+    stars: Vec<Star>,
+    /// Star indices per grid cell, six faces of `GRID_SIZE` squared cells.
+    grid: Vec<Vec<u32>>,
+    milky_way_color: Color,
+    galactic_pole: Vec3,
+    galactic_center: Vec3,
+}
+
+impl NightSky {
+    /// `brightness` is the sun brightness, which every color is divided by.
+    pub fn new(parameters: &NightParameters, catalog: &[CatalogStar], location: &SunLocation, brightness: f64) -> Self {
+        let mut stars = Vec::with_capacity(catalog.len());
+        let mut grid = vec![Vec::new(); 6 * GRID_SIZE * GRID_SIZE];
+
+        for star in catalog {
+            let direction = location.equatorial_direction(star.right_ascension, star.declination);
+            let color = color_from_index(star.color_index) * (parameters.star_brightness * 10.0f64.powf(-0.4 * star.magnitude) / brightness);
+
+            // A star goes into every cell its disk touches, so a lookup only needs the cell of the direction. Points
+            // closer together than any cell, over a square a little larger than the disk, find all of them.
+            let (tangent, bitangent) = tangents(direction);
+            let extent = parameters.star_size + CELL_SAMPLE_SPACING;
+            let steps = (2.0 * extent / CELL_SAMPLE_SPACING).ceil() as usize;
+            let mut cells: Vec<usize> = (0..(steps + 1) * (steps + 1))
+                .map(|k| {
+                    let (a, b) = ((k % (steps + 1)) as f64 / steps as f64 * 2.0 - 1.0, (k / (steps + 1)) as f64 / steps as f64 * 2.0 - 1.0);
+                    grid_cell(direction + (tangent * a + bitangent * b) * extent)
+                })
+                .collect();
+            cells.sort_unstable();
+            cells.dedup();
+
+            for cell in cells {
+                grid[cell].push(stars.len() as u32);
+            }
+            stars.push(Star { direction, color });
+        }
+
+        NightSky {
+            sky_color: parameters.sky_color * (1.0 / brightness),
+            moon_direction: location.moon_direction(),
+            moon_size: parameters.moon_size,
+            moon_color: parameters.moon_color * (parameters.moon_brightness / brightness),
+            star_cos_size: parameters.star_size.cos(),
+            stars,
+            grid,
+            milky_way_color: Color::new(1.0, 0.92, 0.8) * (parameters.milky_way_brightness / brightness),
+            galactic_pole: location.equatorial_direction(GALACTIC_POLE.0, GALACTIC_POLE.1),
+            galactic_center: location.equatorial_direction(GALACTIC_CENTER.0, GALACTIC_CENTER.1),
+        }
+    }
+
+    pub fn sky_color(&self) -> Color {
+        self.sky_color
+    }
+
+    pub fn moon_direction(&self) -> Vec3 {
+        self.moon_direction
+    }
+
+    /// Solid angle of the moon disk, measured like the sun's.
+    pub fn moon_solid_angle(&self) -> f64 {
+        PI * self.moon_size * self.moon_size
+    }
+
+    /// The average color of the moon disk, which is what lights the clouds. A sphere that scatters like a matte
+    /// surface reflects (sin a + (pi - a) cos a) / pi of its full moon light at phase angle a, and a full disk averages
+    /// 2/3 of the color at its center.
+    pub fn moon_light(&self, sun_direction: Vec3) -> Color {
+        let phase_angle = (-self.moon_direction.dot(sun_direction)).clamp(-1.0, 1.0).acos();
+        let phase = (phase_angle.sin() + (PI - phase_angle) * phase_angle.cos()) / PI;
+
+        self.moon_color * (2.0 / 3.0 * phase)
+    }
+
+    /// How far night has fallen, from 0 with the sun on the horizon to 1 once it is 12 degrees below (nautical dusk).
+    pub fn darkness(sun_direction: Vec3) -> f64 {
+        let depression = -sun_direction.y.clamp(-1.0, 1.0).asin().to_degrees();
+        let x = (depression / 12.0).clamp(0.0, 1.0);
+
+        x * x * (3.0 - 2.0 * x)
+    }
+
+    /// The moon disk, shaded as a matte sphere lit by the sun so that it shows the right phase, or `None` if the
+    /// direction misses it. Once the moon has set the ground hides it, as it does the sun.
+    pub fn get_moon_color(&self, direction: Vec3, sun_direction: Vec3) -> Option<Color> {
+        let offset = direction - self.moon_direction;
+        let dist = offset.mag();
+        if dist > self.moon_size || self.moon_direction.y < 0.0 {
+            return None;
+        }
+
+        // Point on the sphere facing the viewer that appears at this offset from the disk center.
+        let (tangent, bitangent) = tangents(self.moon_direction);
+        let (u, v) = (offset.dot(tangent) / self.moon_size, offset.dot(bitangent) / self.moon_size);
+        let w = (1.0 - u * u - v * v).max(0.0).sqrt();
+        let normal = tangent * u + bitangent * v - self.moon_direction * w;
+
+        Some(self.moon_color * normal.dot(sun_direction).max(0.0))
+    }
+
+    /// Stars and the Milky Way on top of the night sky color. Below the horizon the Milky Way mirrors the sky above
+    /// it like the day sky models do, but the stars, like the sun and moon disks, are not mirrored.
+    pub fn get_sky_color(&self, direction: Vec3) -> Color {
+        let mut color = self.sky_color;
+
+        if direction.y >= 0.0 {
+            for &index in self.grid[grid_cell(direction)].iter() {
+                let star = &self.stars[index as usize];
+                if direction.dot(star.direction) >= self.star_cos_size {
+                    color = color + star.color;
+                }
+            }
+        }
+
+        let direction = Vec3::new(direction.x, direction.y.abs(), direction.z);
+
+        // A band along the galactic equator that bulges and brightens toward the center.
+        let latitude = direction.dot(self.galactic_pole).clamp(-1.0, 1.0).asin().to_degrees();
+        let center = direction.dot(self.galactic_center).clamp(-1.0, 1.0).acos().to_degrees();
+        let width = 8.0 + 10.0 * (-(center / 40.0).powi(2)).exp();
+        let band = (-(latitude / width).powi(2)).exp() * (0.4 + 0.6 * (-(center / 60.0).powi(2)).exp());
+
+        color + self.milky_way_color * band
+    }
+}
+
+/// Two unit vectors perpendicular to `direction` and to each other.
+fn tangents(direction: Vec3) -> (Vec3, Vec3) {
+    let helper = if direction.y.abs() < 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+    let tangent = direction.cross(helper).normalize();
+
+    (tangent, direction.cross(tangent))
+}
+
+/// Index of the grid cell a direction falls into: the dominant axis picks the cube face, the other two the cell.
+fn grid_cell(direction: Vec3) -> usize {
+    let (x, y, z) = (direction.x, direction.y, direction.z);
+    let (face, u, v, major) = if x.abs() >= y.abs() && x.abs() >= z.abs() {
+        (if x > 0.0 { 0 } else { 1 }, y, z, x.abs())
+    } else if y.abs() >= z.abs() {
+        (if y > 0.0 { 2 } else { 3 }, x, z, y.abs())
+    } else {
+        (if z > 0.0 { 4 } else { 5 }, x, y, z.abs())
+    };
+
+    let cell = |coordinate: f64| (((coordinate / major + 1.0) / 2.0 * GRID_SIZE as f64) as usize).min(GRID_SIZE - 1);
+
+    (face * GRID_SIZE + cell(u)) * GRID_SIZE + cell(v)
+}
+
+/// Approximate star color from its B-V index, from hot blue-white through the sun's yellow-white to cool orange.
+fn color_from_index(color_index: f64) -> Color {
+    let blue = Color::new(0.62, 0.72, 1.0);
+    let white = Color::new(1.0, 0.96, 0.9);
+    let orange = Color::new(1.0, 0.68, 0.42);
+
+    if color_index < 0.6 {
+        blue.mix(white, ((color_index + 0.3) / 0.9).clamp(0.0, 1.0))
+    } else {
+        white.mix(orange, ((color_index - 0.6) / 1.0).clamp(0.0, 1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sun::DateTime;
+
+    fn parameters(star_size: f64) -> NightParameters {
+        NightParameters {
+            sky_color: Color::new(0.0, 0.0, 0.0),
+            moon_size: 0.08,
+            moon_color: Color::new(1.0, 1.0, 1.0),
+            moon_brightness: 1.0,
+            star_size,
+            star_brightness: 1.0,
+            milky_way_brightness: 0.0,
+        }
+    }
+
+    #[test]
+    fn large_stars_have_no_holes() {
+        let location = SunLocation { latitude: 54.6872, longitude: 25.2797, date_time: DateTime::parse_date("2022-06-21").unwrap().with_time("23:00").unwrap() };
+
+        for star_size in [0.003, 0.03, 0.099] {
+            let night = NightSky::new(&parameters(star_size), &CatalogStar::generate(30, 1), &location, 1.0);
+
+            let visible: Vec<&Star> = night.stars.iter().filter(|star| star.direction.y > 0.2).collect();
+            assert!(!visible.is_empty());
+
+            for star in visible {
+                let (tangent, bitangent) = tangents(star.direction);
+
+                for k in 0..400 {
+                    let (angle, radius) = (k as f64 * 2.399963, (k as f64 / 400.0).sqrt() * 0.99);
+                    let offset = (tangent * angle.cos() + bitangent * angle.sin()) * (star_size * radius).tan();
+                    let direction = (star.direction + offset).normalize();
+
+                    // Overlapping stars only add to the color.
+                    let (color, star_color) = (night.get_sky_color(direction).to_array(), star.color.to_array());
+                    assert!((0..3).all(|channel| color[channel] >= star_color[channel]), "hole in a star of size {}", star_size);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn set_moon_is_hidden() {
+        let catalog = CatalogStar::generate(0, 1);
+        let at = |hour: u32| SunLocation { latitude: 54.6872, longitude: 25.2797, date_time: DateTime::parse_date("2022-06-21").unwrap().with_time(&format!("{}:00", hour)).unwrap() };
+        let nights: Vec<(NightSky, Vec3)> = (0..24).map(|hour| (NightSky::new(&parameters(0.003), &catalog, &at(hour), 1.0), at(hour).direction())).collect();
+
+        let (risen, sun_direction) = nights.iter().find(|(night, _)| night.moon_direction().y > 0.1).unwrap();
+        assert!(risen.get_moon_color(risen.moon_direction(), *sun_direction).is_some());
+
+        let (set, sun_direction) = nights.iter().find(|(night, _)| night.moon_direction().y < -0.1).unwrap();
+        assert!(set.get_moon_color(set.moon_direction(), *sun_direction).is_none());
+        assert_eq!(set.get_sky_color(set.moon_direction()).to_array(), set.sky_color().to_array());
+    }
+}
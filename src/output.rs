@@ -0,0 +1,169 @@
+// Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter};
+use std::path::Path;
+use crate::error::SkyboxError;
+use crate::config::ImagesConfig;
+use crate::image::FaceImage;
+
+/// File format of the output images.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// 16-bit RGB, clamped to [0, 1].
+    Png,
+    /// OpenEXR with unclamped linear radiance.
+    Exr,
+    /// Radiance RGBE with unclamped linear radiance.
+    Hdr,
+}
+
+impl ImageFormat {
+    pub fn from_name(name: &str) -> Option<ImageFormat> {
+        match name {
+            "png" => Some(ImageFormat::Png),
+            "exr" => Some(ImageFormat::Exr),
+            "hdr" => Some(ImageFormat::Hdr),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Exr => "exr",
+            ImageFormat::Hdr => "hdr",
+        }
+    }
+}
+
+/// What to do when an output file already exists.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Fail before anything is rendered.
+    Refuse,
+    /// Replace the existing file.
+    Overwrite,
+    /// Keep the existing file and skip rendering it.
+    Skip,
+}
+
+/// A file name pattern such as `{name}_{cloud_seed}_{face}.{ext}`.
+/// Placeholders are resolved by a lookup function, so any value known to the caller can be used. The lookup returns
+/// `None` for a placeholder it does not know and an error message for one it knows but cannot resolve.
+#[derive(Debug, Clone)]
+pub struct OutputTemplate {
+    template: String,
+}
+
+impl OutputTemplate {
+    pub const DEFAULT: &'static str = "{face}.{ext}";
+    /// The default when rendering a frame sequence.
+    pub const FRAMES_DEFAULT: &'static str = "{face}_{frame}.{ext}";
+
+    pub fn new(template: &str) -> Self {
+        OutputTemplate { template: template.to_string() }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.template
+    }
+
+    pub fn has_placeholder(&self, placeholder: &str) -> bool {
+        self.template.contains(&format!("{{{}}}", placeholder))
+    }
+
+    pub fn expand<F: Fn(&str) -> Result<Option<String>, String>>(&self, lookup: F) -> Result<String, SkyboxError> {
+        let invalid = |reason: String| SkyboxError::InvalidTemplate { template: self.template.clone(), reason };
+
+        let mut result = String::new();
+        let mut rest = self.template.as_str();
+
+        while let Some(start) = rest.find('{') {
+            result.push_str(&rest[..start]);
+
+            let end = rest[start..].find('}').ok_or_else(|| invalid("unclosed \"{\"".to_string()))? + start;
+            let placeholder = &rest[start + 1..end];
+            let value = lookup(placeholder).map_err(&invalid)?.ok_or_else(|| invalid(format!("unknown placeholder \"{{{}}}\"", placeholder)))?;
+            result.push_str(&value);
+
+            rest = &rest[end + 1..];
+        }
+        result.push_str(rest);
+
+        Ok(result)
+    }
+}
+
+/// Opens an output file according to the policy. Existing files are truncated when overwritten so that
+/// a smaller image never leaves trailing bytes from an older, larger one.
+pub fn create_output_file(path: &Path, policy: OverwritePolicy) -> Result<File, SkyboxError> {
+    let mut options = OpenOptions::new();
+    options.write(true);
+
+    if policy == OverwritePolicy::Overwrite {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+
+    options.open(path).map_err(|source| match source.kind() {
+        io::ErrorKind::AlreadyExists => SkyboxError::OutputExists { path: path.to_path_buf() },
+        _ => SkyboxError::Io { path: path.to_path_buf(), source },
+    })
+}
+
+/// Writes the image in the format selected by the configuration.
+pub fn save_image(image: &FaceImage, path: &Path, images: &ImagesConfig, policy: OverwritePolicy) -> Result<(), SkyboxError> {
+    let w = BufWriter::new(create_output_file(path, policy)?);
+    let encoding_error = |source: Box<dyn std::error::Error + Send + Sync>| SkyboxError::Encoding { path: path.to_path_buf(), source };
+
+    match images.format {
+        ImageFormat::Png => image.write_png(w, images.raw_color).map_err(|error| encoding_error(Box::new(error))),
+        ImageFormat::Exr => image.write_exr(w, images.exr_precision, images.exr_compression).map_err(|error| encoding_error(Box::new(error))),
+        ImageFormat::Hdr => image.write_hdr(w).map_err(|source| SkyboxError::Io { path: path.to_path_buf(), source }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(placeholder: &str) -> Result<Option<String>, String> {
+        match placeholder {
+            "face" => Ok(Some("north".to_string())),
+            "ext" => Ok(Some("png".to_string())),
+            "time" => Err("ambiguous".to_string()),
+            _ => Ok(None),
+        }
+    }
+
+    fn reason(result: Result<String, SkyboxError>) -> String {
+        match result {
+            Err(SkyboxError::InvalidTemplate { reason, .. }) => reason,
+            other => panic!("expected an invalid template, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn expands_placeholders() {
+        assert_eq!(OutputTemplate::new(OutputTemplate::DEFAULT).expand(lookup).unwrap(), "north.png");
+        assert_eq!(OutputTemplate::new("sky/{face}-{face}.{ext}").expand(lookup).unwrap(), "sky/north-north.png");
+        assert_eq!(OutputTemplate::new("plain").expand(lookup).unwrap(), "plain");
+    }
+
+    #[test]
+    fn rejects_unknown_unclosed_and_unresolved_placeholders() {
+        assert_eq!(reason(OutputTemplate::new("{face}_{seed}.{ext}").expand(lookup)), "unknown placeholder \"{seed}\"");
+        assert_eq!(reason(OutputTemplate::new("{}.{ext}").expand(lookup)), "unknown placeholder \"{}\"");
+        assert_eq!(reason(OutputTemplate::new("{face.{ext}").expand(lookup)), "unknown placeholder \"{face.{ext}\"");
+        assert_eq!(reason(OutputTemplate::new("{face}.{ext").expand(lookup)), "unclosed \"{\"");
+        assert_eq!(reason(OutputTemplate::new("{time}_{face}").expand(lookup)), "ambiguous");
+    }
+
+    #[test]
+    fn finds_placeholders() {
+        assert!(OutputTemplate::new(OutputTemplate::FRAMES_DEFAULT).has_placeholder("frame"));
+        assert!(!OutputTemplate::new("{face}_frame.{ext}").has_placeholder("frame"));
+    }
+}
@@ -0,0 +1,197 @@
+// Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
+
+use std::f64::consts::PI;
+use crate::vec3::Vec3;
+use crate::fast_rng::Frng;
+
+/// Angular distribution of light scattered by cloud droplets. Used both to pick the direction a path
+/// continues in and to weight sunlight scattered toward the viewer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PhaseFunction {
+    /// The original kernel: the direction is perturbed by a random offset from the unit ball,
+    /// which gives 2 cos^3 / pi over the forward hemisphere and nothing backwards.
+    Legacy,
+    Isotropic,
+    /// Henyey-Greenstein with asymmetry `g` in (-1, 1). Positive values scatter forward.
+    HenyeyGreenstein { g: f64 },
+    /// A blend of a forward and a backward Henyey-Greenstein lobe, `weight` being the share of the forward one.
+    DualLobe { forward_g: f64, backward_g: f64, weight: f64 },
+    /// Cornette-Shanks, a Henyey-Greenstein variant with a Rayleigh-like (1 + cos^2) term that suits water droplets better.
+    CornetteShanks { g: f64 },
+}
+
+impl PhaseFunction {
+    pub fn from_name(name: &str) -> Option<PhaseFunction> {
+        match name {
+            "legacy" => Some(PhaseFunction::Legacy),
+            "isotropic" => Some(PhaseFunction::Isotropic),
+            "henyey_greenstein" => Some(PhaseFunction::HenyeyGreenstein { g: 0.6 }),
+            "dual_lobe" => Some(PhaseFunction::DualLobe { forward_g: 0.6, backward_g: -0.3, weight: 0.8 }),
+            "cornette_shanks" => Some(PhaseFunction::CornetteShanks { g: 0.6 }),
+            _ => None,
+        }
+    }
+
+    /// The same kind of phase function with other asymmetry parameters. Ignored by the kinds that have none.
+    pub fn with_parameters(self, g: f64, backward_g: f64, weight: f64) -> PhaseFunction {
+        match self {
+            PhaseFunction::Legacy | PhaseFunction::Isotropic => self,
+            PhaseFunction::HenyeyGreenstein { .. } => PhaseFunction::HenyeyGreenstein { g },
+            PhaseFunction::DualLobe { .. } => PhaseFunction::DualLobe { forward_g: g, backward_g, weight },
+            PhaseFunction::CornetteShanks { .. } => PhaseFunction::CornetteShanks { g },
+        }
+    }
+
+    /// Probability density per steradian of scattering by an angle with the given cosine.
+    pub fn evaluate(self, cos_theta: f64) -> f64 {
+        match self {
+            PhaseFunction::Legacy => {
+                if cos_theta <= 0.0 {
+                    0.0
+                } else {
+                    2.0 / PI * cos_theta.powi(3)
+                }
+            },
+            PhaseFunction::Isotropic => 1.0 / (4.0 * PI),
+            PhaseFunction::HenyeyGreenstein { g } => henyey_greenstein(g, cos_theta),
+            PhaseFunction::DualLobe { forward_g, backward_g, weight } => {
+                weight * henyey_greenstein(forward_g, cos_theta) + (1.0 - weight) * henyey_greenstein(backward_g, cos_theta)
+            },
+            PhaseFunction::CornetteShanks { g } => {
+                let g2 = g * g;
+                3.0 * (1.0 - g2) * (1.0 + cos_theta * cos_theta) / (8.0 * PI * (2.0 + g2) * (1.0 + g2 - 2.0 * g * cos_theta).powf(1.5))
+            },
+        }
+    }
+
+    /// Picks a new direction for light travelling along `direction`, distributed exactly as `evaluate`.
+    pub(crate) fn sample(self, direction: Vec3, frng: &mut Frng) -> Vec3 {
+        match self {
+            PhaseFunction::Legacy => {
+                let mut change;
+
+                loop {
+                    change = Vec3::new(frng.next_double(-1.0, 1.0), frng.next_double(-1.0, 1.0), frng.next_double(-1.0, 1.0));
+
+                    if change.mag_squared() < 1.0 {
+                        break;
+                    }
+                }
+
+                (direction + change).normalize()
+            },
+            PhaseFunction::Isotropic => rotate(direction, frng.next_double(-1.0, 1.0), frng),
+            PhaseFunction::HenyeyGreenstein { g } => rotate(direction, sample_henyey_greenstein(g, frng), frng),
+            PhaseFunction::DualLobe { forward_g, backward_g, weight } => {
+                let g = if frng.next_double_default() < weight { forward_g } else { backward_g };
+
+                rotate(direction, sample_henyey_greenstein(g, frng), frng)
+            },
+            PhaseFunction::CornetteShanks { g } => {
+                // Cornette-Shanks is Henyey-Greenstein times (1 + cos^2) / 2 up to a constant, so HG samples can be thinned by rejection.
+                loop {
+                    let cos_theta = sample_henyey_greenstein(g, frng);
+
+                    if 2.0 * frng.next_double_default() < 1.0 + cos_theta * cos_theta {
+                        break rotate(direction, cos_theta, frng);
+                    }
+                }
+            },
+        }
+    }
+}
+
+fn henyey_greenstein(g: f64, cos_theta: f64) -> f64 {
+    let g2 = g * g;
+
+    (1.0 - g2) / (4.0 * PI * (1.0 + g2 - 2.0 * g * cos_theta).powf(1.5))
+}
+
+/// Inverts the Henyey-Greenstein distribution for the cosine of the scattering angle.
+fn sample_henyey_greenstein(g: f64, frng: &mut Frng) -> f64 {
+    let u = frng.next_double_default();
+
+    if g.abs() < 1e-3 {
+        return 1.0 - 2.0 * u;
+    }
+
+    let g2 = g * g;
+    let s = (1.0 - g2) / (1.0 - g + 2.0 * g * u);
+
+    ((1.0 + g2 - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+}
+
+/// Turns `direction` by an angle with the given cosine, around it by a random azimuth.
+fn rotate(direction: Vec3, cos_theta: f64, frng: &mut Frng) -> Vec3 {
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = frng.next_double(0.0, 2.0 * PI);
+
+    let helper = if direction.x.abs() < 0.9 { Vec3::new(1.0, 0.0, 0.0) } else { Vec3::new(0.0, 1.0, 0.0) };
+    let tangent = direction.cross(helper).normalize();
+    let bitangent = direction.cross(tangent);
+
+    (cos_theta * direction + sin_theta * phi.cos() * tangent + sin_theta * phi.sin() * bitangent).normalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PHASE_FUNCTIONS: [PhaseFunction; 8] = [
+        PhaseFunction::Legacy,
+        PhaseFunction::Isotropic,
+        PhaseFunction::HenyeyGreenstein { g: 0.6 },
+        PhaseFunction::HenyeyGreenstein { g: -0.4 },
+        PhaseFunction::HenyeyGreenstein { g: 0.95 },
+        PhaseFunction::DualLobe { forward_g: 0.6, backward_g: -0.3, weight: 0.8 },
+        PhaseFunction::CornetteShanks { g: 0.6 },
+        PhaseFunction::CornetteShanks { g: -0.3 },
+    ];
+
+    /// Probability of scattering by an angle whose cosine lies between `a` and `b`, by the midpoint rule.
+    fn probability(phase: PhaseFunction, a: f64, b: f64) -> f64 {
+        let steps = 20_000;
+        let width = (b - a) / steps as f64;
+
+        (0..steps).map(|step| 2.0 * PI * phase.evaluate(a + (step as f64 + 0.5) * width) * width).sum()
+    }
+
+    #[test]
+    fn evaluate_integrates_to_one() {
+        for phase in PHASE_FUNCTIONS {
+            let total = probability(phase, -1.0, 0.0) + probability(phase, 0.0, 1.0);
+
+            assert!((total - 1.0).abs() < 1e-4, "{:?} integrates to {}", phase, total);
+        }
+    }
+
+    #[test]
+    fn samples_follow_evaluate() {
+        const BINS: usize = 20;
+        const SAMPLES: usize = 200_000;
+
+        let direction = Vec3::new(0.3, -0.5, 0.8).normalize();
+
+        for phase in PHASE_FUNCTIONS {
+            let mut frng = Frng::new(11);
+            let mut histogram = [0usize; BINS];
+
+            for _ in 0..SAMPLES {
+                let sample = phase.sample(direction, &mut frng);
+                assert!((sample.mag() - 1.0).abs() < 1e-9);
+
+                let cos_theta = sample.dot(direction).clamp(-1.0, 1.0);
+                histogram[(((cos_theta + 1.0) / 2.0 * BINS as f64) as usize).min(BINS - 1)] += 1;
+            }
+
+            for (bin, &count) in histogram.iter().enumerate() {
+                let a = bin as f64 * 2.0 / BINS as f64 - 1.0;
+                let expected = probability(phase, a, a + 2.0 / BINS as f64);
+                let sigma = (expected * (1.0 - expected) / SAMPLES as f64).sqrt();
+                let observed = count as f64 / SAMPLES as f64;
+
+                assert!((observed - expected).abs() < 5.0 * sigma + 1e-3, "{:?}, cosines {} to {}: {} instead of {}", phase, a, a + 2.0 / BINS as f64, observed, expected);
+            }
+        }
+    }
+}
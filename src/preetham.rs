@@ -0,0 +1,85 @@
+// Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
+
+use std::f64::consts::{FRAC_PI_2, PI};
+use crate::vec3::Vec3;
+use crate::color::Color;
+
+/// Converts the model's kilocandelas per square metre to the units of the atmosphere model, so that the same
+/// `sky_intensity` gives a similar zenith brightness with the sun at 35 degrees.
+const LUMINANCE_SCALE: f64 = 1.0 / 440.0;
+
+/// The analytic daylight model from Preetham, Shirley and Smits, "A Practical Analytic Model for Daylight".
+/// Luminance and chromaticity follow the Perez sky distribution, fitted over turbidity and the sun position.
+#[derive(Debug, Clone)]
+pub struct Preetham {
+    sun_direction: Vec3,
+    /// Perez coefficients A to E for the luminance Y and the chromaticities x and y.
+    coefficients: [[f64; 5]; 3],
+    /// Y, x and y at the zenith divided by the Perez function there, so that a direction only needs one more evaluation.
+    zenith: [f64; 3],
+}
+
+impl Preetham {
+    pub fn new(turbidity: f64, sun_direction: Vec3) -> Self {
+        let t = turbidity;
+        let coefficients = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+
+        // The fit only covers the sun above the horizon.
+        let sun_theta = sun_direction.y.clamp(0.0, 1.0).acos();
+        let polynomial = |c: [f64; 4]| c[0] * sun_theta.powi(3) + c[1] * sun_theta.powi(2) + c[2] * sun_theta + c[3];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * sun_theta);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let zenith_x = t * t * polynomial([0.00166, -0.00375, 0.00209, 0.0])
+            + t * polynomial([-0.02903, 0.06377, -0.03202, 0.00394])
+            + polynomial([0.11693, -0.21196, 0.06052, 0.25886]);
+        let zenith_yy = t * t * polynomial([0.00275, -0.00610, 0.00317, 0.0])
+            + t * polynomial([-0.04214, 0.08970, -0.04153, 0.00516])
+            + polynomial([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        let zenith = [
+            zenith_y / perez(coefficients[0], 0.0, sun_theta),
+            zenith_x / perez(coefficients[1], 0.0, sun_theta),
+            zenith_yy / perez(coefficients[2], 0.0, sun_theta),
+        ];
+
+        Preetham { sun_direction, coefficients, zenith }
+    }
+
+    /// Linear RGB sky color in the direction. Directions below the horizon mirror those above it.
+    pub fn get_sky_color(&self, direction: Vec3) -> Color {
+        let theta = direction.y.abs().min(1.0).acos().min(FRAC_PI_2 - 0.01);
+        let gamma = direction.dot(self.sun_direction).clamp(-1.0, 1.0).acos();
+
+        let luminance = self.zenith[0] * perez(self.coefficients[0], theta, gamma);
+        let x = self.zenith[1] * perez(self.coefficients[1], theta, gamma);
+        let y = self.zenith[2] * perez(self.coefficients[2], theta, gamma);
+
+        xyy_to_rgb(x, y, luminance * LUMINANCE_SCALE)
+    }
+}
+
+/// The Perez sky distribution by view zenith angle `theta` and angle from the sun `gamma`.
+fn perez(c: [f64; 5], theta: f64, gamma: f64) -> f64 {
+    (1.0 + c[0] * (c[1] / theta.cos()).exp()) * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos().powi(2))
+}
+
+/// Converts CIE xyY to linear sRGB.
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Color {
+    if y <= 0.0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+
+    Color::new(
+        (3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z).max(0.0),
+        (-0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z).max(0.0),
+        (0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z).max(0.0),
+    )
+}
@@ -0,0 +1,75 @@
+// Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// A snapshot of a render in progress, passed to the progress callback after every finished tile.
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub completed_tiles: usize,
+    pub total_tiles: usize,
+    pub elapsed: Duration,
+    pub views: Vec<ViewProgress>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ViewProgress {
+    pub name: &'static str,
+    pub completed_tiles: usize,
+    pub total_tiles: usize,
+}
+
+impl Progress {
+    pub fn fraction(&self) -> f64 {
+        if self.total_tiles == 0 {
+            return 1.0;
+        }
+
+        self.completed_tiles as f64 / self.total_tiles as f64
+    }
+
+    /// Estimated time left, extrapolated from the average time per tile so far.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.completed_tiles == 0 {
+            return None;
+        }
+
+        Some(self.elapsed.mul_f64((self.total_tiles - self.completed_tiles) as f64 / self.completed_tiles as f64))
+    }
+}
+
+impl ViewProgress {
+    pub fn fraction(&self) -> f64 {
+        if self.total_tiles == 0 {
+            return 1.0;
+        }
+
+        self.completed_tiles as f64 / self.total_tiles as f64
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.completed_tiles == self.total_tiles
+    }
+}
+
+/// A flag shared between the caller and a running render. Once cancelled, workers stop taking new tiles
+/// and the render returns `SkyboxError::Cancelled`.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
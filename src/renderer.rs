@@ -1,19 +1,40 @@
 // Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
 
-use std::ops::Mul;
 use crate:: vec3::Vec3;
 use crate:: ray3::Ray3;
 use crate:: color::Color;
 use crate:: camera::Camera;
-use crate:: cloud::Cloud;
+use crate:: cloud::CloudLayers;
 use crate:: background::Background;
 use crate:: fast_rng::Frng;
+use crate:: phase::PhaseFunction;
 
+/// How a `Renderer` marches through the clouds and samples each pixel.
+#[derive(Debug, Clone)]
+pub struct RenderParameters {
+    pub image_width: usize,
+    pub image_height: usize,
+    pub min_fog_dist: f64,
+    pub max_fog_dist: f64,
+    pub step_size: f64,
+    pub step_count: usize,
+    pub pixel_width: usize,
+    pub bundle_size: usize,
+    /// Extinction coefficient at full density, per unit of distance.
+    pub extinction: f64,
+    /// Sample the sun directly at every scattering point instead of relying on random paths to hit it.
+    pub direct_sun: bool,
+    pub light_step_count: usize,
+    pub light_step_size: f64,
+    pub phase_function: PhaseFunction,
+    pub seed: u64,
+}
+
+/// Traces the pixels of one view. It knows nothing of tiles or threads: `Skybox` schedules the tiles and reports
+/// their progress.
 #[derive(Debug, Clone)]
 pub struct Renderer {
-    cloud: Cloud,
-    slice_length: usize,
-    color_byte_size: usize,
+    clouds: CloudLayers,
     image_width: usize,
     image_height: usize,
     min_fog_dist: f64,
@@ -23,158 +44,213 @@ pub struct Renderer {
     pixel_width: usize,
     bundle_size: usize,
     background: Background,
-    output_raw_color: bool,
-    sun_brightness: f64,
+    extinction: f64,
+    direct_sun: bool,
+    light_step_count: usize,
+    light_step_size: f64,
+    phase_function: PhaseFunction,
+    seed: u64,
 }
 
 impl Renderer {
-    pub fn new(cloud: Cloud, slice_length: usize, color_byte_size: usize, image_width: usize, image_height: usize, min_fog_dist: f64, max_fog_dist: f64, step_size: f64, step_count: usize, pixel_width: usize, bundle_size: usize, background: Background, output_raw_color: bool, sun_brightness: f64) -> Self {
-        Renderer { cloud, slice_length, color_byte_size, image_width, image_height, min_fog_dist, max_fog_dist, step_size, step_count, pixel_width, bundle_size, background, output_raw_color, sun_brightness }
-    }
-
-    pub fn render_slice(&self, camera: &Camera, min_py: usize, frng_seed: u64) -> Vec<u8> {
-        let mut frng = Frng::new(frng_seed);
-
-        let mut result = vec![0; self.slice_length];
-
-        for color_index in 0..self.slice_length / self.color_byte_size {
-            let px = color_index % self.image_width;
-            let py = min_py + color_index / self.image_width;
-
-            let color = self.trace_pixel(camera, px, py, &mut frng);
+    pub fn new(clouds: CloudLayers, background: Background, parameters: RenderParameters) -> Self {
+        let RenderParameters { image_width, image_height, min_fog_dist, max_fog_dist, step_size, step_count, pixel_width, bundle_size, extinction, direct_sun, light_step_count, light_step_size, phase_function, seed } = parameters;
 
-            let color2 = if self.output_raw_color {
-                color.to_lin_48_u8()
-            } else {
-                color.mul(self.sun_brightness).to_srgb_48_u8()
-            };
+        Renderer { clouds, image_width, image_height, min_fog_dist, max_fog_dist, step_size, step_count, pixel_width, bundle_size, background, extinction, direct_sun, light_step_count, light_step_size, phase_function, seed }
+    }
 
-            let i1 = color_index * self.color_byte_size;
-            let i2 = i1 + self.color_byte_size;
+    /// Renders a rectangle of pixels row by row. The colors are linear and relative to the sun brightness.
+    pub fn render_tile(&self, camera: &Camera, view_id: u64, x: usize, y: usize, width: usize, height: usize) -> Vec<Color> {
+        let mut result = Vec::with_capacity(width * height);
 
-            result[i1..i2].copy_from_slice(&color2);
+        for py in y..y + height {
+            for px in x..x + width {
+                result.push(self.trace_pixel(camera, view_id, px, py));
+            }
         }
 
         result
     }
 
-    fn trace_pixel(&self, camera: &Camera, px: usize, py: usize, frng: &mut Frng) -> Color {
+    /// Every sample gets its own random stream keyed by the renderer seed, view, pixel and sample index,
+    /// so a pixel comes out the same no matter how the image is split up or which views are rendered.
+    pub fn trace_pixel(&self, camera: &Camera, view_id: u64, px: usize, py: usize) -> Color {
         let mut color_sum = Color::new(0.0, 0.0, 0.0);
 
         for spy in 0..self.pixel_width {
             for spx in 0..self.pixel_width {
-                let mut ray = camera.get_ray(
+                let sample_index = spy * self.pixel_width + spx;
+                let mut frng = Frng::from_key(&[self.seed, view_id, px as u64, py as u64, sample_index as u64]);
+
+                let ray = camera.get_ray(
                     (px as f64 + (spx as f64 + 0.5) / self.pixel_width as f64) / self.image_width as f64,
                     (py as f64 + (spy as f64 + 0.5) / self.pixel_width as f64) / self.image_height as f64,
                 );
 
-                color_sum = color_sum + self.trace_bundle(&mut ray, frng);
+                color_sum = color_sum + self.trace_bundle(&ray, &mut frng);
             }
         }
 
         color_sum / (self.pixel_width * self.pixel_width) as f64
     }
 
-    fn trace_bundle(&self, ray: &mut Ray3, frng: &mut Frng) -> Color {
+    /// Marches the camera ray through the cloud layers from the lowest up. The first layer that scatters it
+    /// decides its color; if none does, the sky shows through.
+    fn trace_bundle(&self, ray: &Ray3, frng: &mut Frng) -> Color {
         if ray.direction.y <= 0.0 {
             return self.background.get_sky_color(ray.direction);
         }
 
-        let dist_to_cloud = (self.cloud.min_height - ray.origin.y ) / ray.direction.y + frng.next_double(0.0, self.step_size);
+        for layer in self.clouds.layers() {
+            if ray.origin.y > layer.max_height {
+                continue;
+            }
 
-        ray.origin = ray.origin + ray.direction * dist_to_cloud;
+            let dist_to_cloud = ((layer.min_height - ray.origin.y) / ray.direction.y).max(0.0) + frng.next_double(0.0, self.step_size);
 
-        if dist_to_cloud <= self.min_fog_dist {
-            self.inner_trace_bundle(ray, frng)
-        } else if dist_to_cloud >= self.max_fog_dist {
-            self.background.get_sky_color(ray.direction)
-        } else {
-            let s_color = self.background.get_sky_color(ray.direction);
-            let c_color = self.inner_trace_bundle(ray, frng);
-            let mut fog_amount = (dist_to_cloud + ray.length - self.min_fog_dist) / self.max_fog_dist;
-            if fog_amount > 1.0 {
-                fog_amount = 1.0;
+            // Higher layers are further away still.
+            if dist_to_cloud >= self.max_fog_dist {
+                break;
             }
 
-            c_color.mix(s_color, fog_amount)
+            let mut bundle = Ray3::new(ray.origin + ray.direction * dist_to_cloud, ray.direction);
+
+            if let Some(c_color) = self.inner_trace_bundle(&mut bundle, layer.max_height, frng) {
+                if dist_to_cloud <= self.min_fog_dist {
+                    return c_color;
+                }
+
+                let s_color = self.background.get_sky_color(ray.direction);
+                let mut fog_amount = (dist_to_cloud + bundle.length - self.min_fog_dist) / self.max_fog_dist;
+                if fog_amount > 1.0 {
+                    fog_amount = 1.0;
+                }
+
+                return c_color.mix(s_color, fog_amount);
+            }
         }
-    }
 
-    fn inner_trace_bundle(&self, bundle: &mut Ray3, frng: &mut Frng) -> Color {
-        let mut colors: Option<Vec<Color>> = None;
+        self.background.get_sky_color(ray.direction)
+    }
 
+    /// Marches a bundle through one layer until it scatters, or returns `None` if it leaves through the top
+    /// at `max_height` or runs out of steps first.
+    fn inner_trace_bundle(&self, bundle: &mut Ray3, max_height: f64, frng: &mut Frng) -> Option<Color> {
         for step in 0..self.step_count {
-            if self.cloud.get_density(bundle.origin) {
-                let mut colors_ = vec![Color::new(0.0, 0.0, 0.0); self.bundle_size as usize]; //@@@
-
-                for ray_index in 0..colors_.len() {
-                    let mut ray = bundle.clone();
+            if bundle.origin.y > max_height {
+                break;
+            }
 
-                    let mut change;
+            if self.scatters(bundle.origin, frng) {
+                let direct = self.sample_lights(bundle.origin, bundle.direction);
 
-                    loop {
-                        change = Vec3::new(frng.next_double(-1.0, 1.0), frng.next_double(-1.0, 1.0), frng.next_double(-1.0, 1.0));
+                let mut color_sum = Color::new(0.0, 0.0, 0.0);
 
-                        if change.mag_squared() < 1.0 {
-                            break;
-                        }
-                    }
+                for _ in 0..self.bundle_size {
+                    let mut ray = bundle.clone();
 
-                    ray.direction = (ray.direction + change).normalize();
+                    ray.direction = self.phase_function.sample(ray.direction, frng);
 
                     ray.origin = ray.origin + ray.direction * self.step_size;
 
-                    colors_[ray_index] = self.trace_ray(&mut ray, frng, self.step_count - step - 1);
+                    color_sum = color_sum + self.trace_ray(&mut ray, frng, self.step_count - step - 1);
                 }
 
-                colors = Some(colors_);
+                return Some(direct + color_sum / self.bundle_size as f64);
+            }
 
-                break;
-            } else {
-                bundle.origin = bundle.origin + bundle.direction * self.step_size;
+            bundle.origin = bundle.origin + bundle.direction * self.step_size;
 
-                bundle.length += self.step_size;
-            }
+            bundle.length += self.step_size;
         }
 
-        // compute color
-        match colors {
-            Some(colors) => {
-                let mut color_sum = Color::new(0.0, 0.0, 0.0);
+        None
+    }
+
+    fn trace_ray(&self, ray: &mut Ray3, frng: &mut Frng, steps: usize) -> Color {
+        let mut direct = Color::new(0.0, 0.0, 0.0);
 
-                for i in 0..colors.len() {
-                    color_sum = color_sum + colors[i];
+        for _step in 0..steps {
+            // Between layers the path skips ahead to the next one it meets.
+            if !self.clouds.contains(ray.origin.y) {
+                match self.clouds.distance_to_next_layer(ray.origin, ray.direction) {
+                    Some(distance) => ray.origin = ray.origin + ray.direction * distance,
+                    None => break,
                 }
+            }
+
+            if self.scatters(ray.origin, frng) {
+                direct = direct + self.sample_lights(ray.origin, ray.direction);
+
+                ray.direction = self.phase_function.sample(ray.direction, frng);
+            }
 
-                color_sum / colors.len() as f64
-            },
-            None => self.background.get_sky_color(bundle.direction),
+            ray.origin = ray.origin + ray.direction * self.step_size;
+        }
+
+        if self.direct_sun {
+            direct + self.background.get_background_color_without_lights(ray.direction)
+        } else {
+            self.background.get_background_color(ray.direction)
         }
     }
 
-    fn trace_ray(&self, ray: &mut Ray3, frng: &mut Frng, steps: usize) -> Color {
-        for _step in 0..steps {
-            if ray.origin.y < self.cloud.min_height || ray.origin.y > self.cloud.max_height {
-                break;
+    fn sample_lights(&self, position: Vec3, direction: Vec3) -> Color {
+        let mut color = Color::new(0.0, 0.0, 0.0);
+
+        if !self.direct_sun {
+            return color;
+        }
+
+        for light in self.background.lights() {
+            // Below the horizon the ground blocks the light.
+            if light.direction.y <= 0.0 {
+                continue;
+            }
+
+            let phase = self.phase_function.evaluate(direction.dot(light.direction));
+            if phase <= 0.0 {
+                continue;
             }
 
-            if self.cloud.get_density(ray.origin) {
-                let mut change;
+            color = color + light.color * (light.solid_angle * phase * self.transmittance(position, light.direction));
+        }
+
+        color
+    }
 
-                loop {
-                    change = Vec3::new(frng.next_double(-1.0, 1.0), frng.next_double(-1.0, 1.0), frng.next_double(-1.0, 1.0));
+    /// Beer-Lambert transmittance from a point toward a light, marched in `light_step_count` steps through the
+    /// cloud layers in the way.
+    fn transmittance(&self, origin: Vec3, direction: Vec3) -> f64 {
+        let mut optical_depth = 0.0;
+        let mut position = origin;
 
-                    if change.mag_squared() < 1.0 {
-                        break;
-                    }
-                }
+        for _step in 0..self.light_step_count {
+            position = position + direction * self.light_step_size;
 
-                ray.direction = (ray.direction + change).normalize();
+            if !self.clouds.contains(position.y) {
+                match self.clouds.distance_to_next_layer(position, direction) {
+                    Some(distance) => position = position + direction * distance,
+                    None => break,
+                }
             }
 
-            ray.origin = ray.origin + ray.direction * self.step_size;
+            optical_depth += self.extinction * self.clouds.get_density(position) * self.light_step_size;
         }
 
-        self.background.get_background_color(ray.direction)
+        (-optical_depth).exp()
+    }
+
+    /// Decides whether light is scattered within one step at the given position. Following the Beer-Lambert law,
+    /// the chance is one minus the transmittance of a step through the local density.
+    fn scatters(&self, position: Vec3, frng: &mut Frng) -> bool {
+        let density = self.clouds.get_density(position);
+        if density <= 0.0 {
+            return false;
+        }
+
+        let transmittance = (-self.extinction * density * self.step_size).exp();
+
+        frng.next_double_default() >= transmittance
     }
 }
\ No newline at end of file
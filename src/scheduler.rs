@@ -0,0 +1,173 @@
+// Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
+
+#[cfg(not(feature = "no-multithreading"))]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(not(feature = "no-multithreading"))]
+use std::sync::mpsc;
+#[cfg(not(feature = "no-multithreading"))]
+use std::thread;
+use crate::color::Color;
+use crate::progress::CancellationToken;
+
+/// A rectangle of pixels in one of the views being rendered.
+#[derive(Debug, Copy, Clone)]
+pub struct Tile {
+    pub view_index: usize,
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Splits `view_count` images into square tiles. Tiles on the right and bottom edges may be smaller.
+pub fn make_tiles(view_count: usize, image_width: usize, image_height: usize, tile_size: usize) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+
+    for view_index in 0..view_count {
+        for y in (0..image_height).step_by(tile_size) {
+            for x in (0..image_width).step_by(tile_size) {
+                tiles.push(Tile {
+                    view_index,
+                    x,
+                    y,
+                    width: tile_size.min(image_width - x),
+                    height: tile_size.min(image_height - y),
+                });
+            }
+        }
+    }
+
+    tiles
+}
+
+/// The number of worker threads to use when the configuration leaves it up to the machine.
+pub fn available_threads() -> usize {
+    if cfg!(feature = "no-multithreading") {
+        return 1;
+    }
+
+    std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+/// Renders every tile with a pool of `thread_count` workers. Each worker takes the next unrendered tile
+/// from a shared counter, so cheap and expensive tiles even out and all workers stay busy until the end.
+/// `on_tile` runs on the calling thread as tiles complete, in completion order.
+/// The token is checked before each tile; returns false if the render was cancelled before all tiles were done.
+pub fn render_tiles<R, F>(tiles: &[Tile], thread_count: usize, cancel: &CancellationToken, render: R, mut on_tile: F) -> bool
+where
+    R: Fn(&Tile) -> Vec<Color> + Sync,
+    F: FnMut(&Tile, Vec<Color>),
+{
+    if thread_count <= 1 || cfg!(feature = "no-multithreading") {
+        for tile in tiles.iter() {
+            if cancel.is_cancelled() {
+                return false;
+            }
+
+            on_tile(tile, render(tile));
+        }
+        return true;
+    }
+
+    render_tiles_multithreaded(tiles, thread_count, cancel, render, on_tile)
+}
+
+#[cfg(not(feature = "no-multithreading"))]
+fn render_tiles_multithreaded<R, F>(tiles: &[Tile], thread_count: usize, cancel: &CancellationToken, render: R, mut on_tile: F) -> bool
+where
+    R: Fn(&Tile) -> Vec<Color> + Sync,
+    F: FnMut(&Tile, Vec<Color>),
+{
+    let next_tile = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..thread_count.min(tiles.len()) {
+            let transmitter = tx.clone();
+            let next_tile = &next_tile;
+            let render = &render;
+
+            scope.spawn(move || loop {
+                if cancel.is_cancelled() {
+                    break;
+                }
+
+                let tile_index = next_tile.fetch_add(1, Ordering::Relaxed);
+                if tile_index >= tiles.len() {
+                    break;
+                }
+
+                let pixels = render(&tiles[tile_index]);
+
+                if transmitter.send((tile_index, pixels)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(tx);
+
+        let mut completed = 0;
+        for (tile_index, pixels) in rx {
+            on_tile(&tiles[tile_index], pixels);
+            completed += 1;
+        }
+
+        completed == tiles.len()
+    })
+}
+
+#[cfg(feature = "no-multithreading")]
+fn render_tiles_multithreaded<R, F>(_tiles: &[Tile], _thread_count: usize, _cancel: &CancellationToken, _render: R, _on_tile: F) -> bool
+where
+    R: Fn(&Tile) -> Vec<Color> + Sync,
+    F: FnMut(&Tile, Vec<Color>),
+{
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn cancelling_stops_taking_tiles() {
+        let tiles = make_tiles(2, 40, 40, 4);
+
+        for thread_count in [1, 4] {
+            let cancel = CancellationToken::new();
+            let (rendered, late) = (AtomicUsize::new(0), AtomicUsize::new(0));
+            let mut completed = 0;
+
+            let finished = render_tiles(
+                &tiles,
+                thread_count,
+                &cancel,
+                |tile| {
+                    if cancel.is_cancelled() {
+                        late.fetch_add(1, Ordering::Relaxed);
+                    }
+                    rendered.fetch_add(1, Ordering::Relaxed);
+                    std::thread::sleep(Duration::from_millis(1));
+
+                    vec![Color::new(0.0, 0.0, 0.0); tile.width * tile.height]
+                },
+                |_, _| {
+                    completed += 1;
+                    if completed == 10 {
+                        cancel.cancel();
+                    }
+                },
+            );
+
+            // A worker may take one more tile if it checked the token just before the cancellation.
+            assert!(!finished, "{} threads", thread_count);
+            assert!(late.load(Ordering::Relaxed) <= thread_count, "{} threads", thread_count);
+            assert_eq!(completed, rendered.load(Ordering::Relaxed), "{} threads", thread_count);
+            if thread_count == 1 || cfg!(feature = "no-multithreading") {
+                assert_eq!((completed, late.load(Ordering::Relaxed)), (10, 0));
+            }
+        }
+    }
+}
@@ -0,0 +1,325 @@
+// Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
+
+use std::time::{Duration, Instant};
+use rand::{RngCore, SeedableRng};
+use rand_xoshiro::Xoshiro256PlusPlus;
+use crate::color::Color;
+use crate::camera::Camera;
+use crate::cloud::{Cloud, CloudLayers, CloudParameters};
+use crate::coverage::{Coverage, CoverageMap, CoverageMode};
+use crate::background::{Background, SkyModel};
+use crate::hosek_wilkie::HosekWilkieData;
+use crate::sun::{direction_from_angles, SunMode};
+use crate::night::{CatalogStar, NightParameters, NightSky};
+use crate::renderer::{RenderParameters, Renderer};
+use crate::face::Face;
+use crate::view::{Projection, View};
+use crate::layout::{CubemapConvention, CubemapLayout};
+use crate::image::FaceImage;
+use crate::progress::{CancellationToken, Progress, ViewProgress};
+use crate::scheduler::{available_threads, make_tiles, render_tiles};
+use crate::config::SkyboxConfig;
+use crate::error::SkyboxError;
+
+/// A fully initialized skybox scene. Faces are rendered into memory one at a time.
+#[derive(Debug, Clone)]
+pub struct Skybox {
+    renderer: Renderer,
+    brightness: f64,
+    views: Vec<View>,
+    layout: CubemapLayout,
+    convention: CubemapConvention,
+    image_width: usize,
+    image_height: usize,
+    thread_count: usize,
+    tile_size: usize,
+}
+
+impl Skybox {
+    /// Validates the configuration and builds the scene it describes.
+    pub fn new(config: &SkyboxConfig) -> Result<Self, SkyboxError> {
+        config.validate()?;
+
+        let images = &config.images;
+        let clouds = &config.clouds;
+        let background = &config.background;
+        let quality = &config.quality;
+
+        let thread_count = match (config.slices.use_multithreading, config.slices.thread_count) {
+            (false, _) => 1,
+            (true, 0) => available_threads(),
+            (true, thread_count) => thread_count,
+        };
+
+        let views = match images.projection {
+            Projection::Equirectangular => vec![View::Panorama],
+            Projection::Cubemap if images.render_north_only => vec![View::Face(Face::North)],
+            Projection::Cubemap => Face::ALL.iter().map(|&face| View::Face(face)).collect(),
+        };
+
+        let layers = clouds.layers.iter().map(|layer| {
+            let mut clouds_rng = Xoshiro256PlusPlus::seed_from_u64(layer.cloud_seed);
+
+            let cloud = Cloud::new(&CloudParameters {
+                min_height: layer.min_height,
+                max_height: layer.max_height,
+                threshold: layer.cloud_threshold,
+                softness: layer.cloud_softness,
+                levels: layer.noise_levels,
+                seed: clouds_rng.next_u32() as i32,
+                scale: 256.0 / 2.0f64.powi(layer.noise_levels as i32) * layer.noise_scale,
+                density: layer.density,
+                profile: layer.height_profile.clone(),
+                noise: layer.noise_type,
+                octave_weights: layer.octave_weights.clone(),
+                warp: layer.warp,
+                warp_strength: layer.warp_strength,
+                warp_scale: layer.warp_scale,
+            })?.with_motion(layer.wind_x, layer.wind_z, layer.evolution, clouds.time);
+
+            let map = match (layer.coverage, &layer.coverage_image) {
+                (CoverageMode::None, _) => return Ok(cloud),
+                (CoverageMode::Image, Some(path)) => CoverageMap::read_png(path)?,
+                (CoverageMode::Image, None) => return Err(SkyboxError::MissingKey { section: layer.section.clone(), key: "coverage_image".to_string() }),
+                (CoverageMode::Procedural, _) => CoverageMap::procedural(clouds_rng.next_u32() as i32),
+            };
+            let coverage = Coverage::new(map, layer.coverage_scale, layer.coverage_offset_x, layer.coverage_offset_z, layer.coverage_tiling);
+
+            Ok(cloud.with_coverage(coverage, layer.coverage_strength, layer.coverage_profile.clone()))
+        }).collect::<Result<Vec<Cloud>, SkyboxError>>()?;
+
+        let sky_colors = background.sky_colors.iter().map(|&color| color * (1.0 / background.sun_brightness)).collect();
+        let ground_color = background.ground_color * (1.0 / background.sun_brightness);
+        let sun_direction = match config.sun.mode {
+            SunMode::Angles => direction_from_angles(background.sun_angle_phi, background.sun_angle_theta),
+            SunMode::Location => config.sun.location.direction(),
+        };
+        let mut background_ = Background::new(background.sun_size, background.sun_color, sky_colors, ground_color, sun_direction);
+        let night = &config.night;
+        if night.enabled {
+            let catalog = match &night.star_catalog {
+                Some(path) => CatalogStar::read_catalog(path)?,
+                None => CatalogStar::generate(night.star_count, night.star_seed),
+            };
+            let parameters = NightParameters {
+                sky_color: night.sky_color,
+                moon_size: night.moon_size,
+                moon_color: night.moon_color,
+                moon_brightness: night.moon_brightness,
+                star_size: night.star_size,
+                star_brightness: night.star_brightness,
+                milky_way_brightness: if night.milky_way { night.milky_way_brightness } else { 0.0 },
+            };
+
+            background_ = background_.with_night(NightSky::new(&parameters, &catalog, &config.sun.location, background.sun_brightness));
+        }
+
+        let sky_intensity = background.sky_intensity / background.sun_brightness;
+        match (background.sky_model, &background.hosek_wilkie_data) {
+            (SkyModel::Gradient, _) => {},
+            (SkyModel::Atmosphere, _) => background_ = background_.with_atmosphere(background.atmosphere, sky_intensity),
+            (SkyModel::Preetham, _) => background_ = background_.with_preetham(background.atmosphere.turbidity, sky_intensity),
+            (SkyModel::HosekWilkie, Some(path)) => {
+                let data = HosekWilkieData::from_file(path)?;
+                background_ = background_.with_hosek_wilkie(&data, background.atmosphere.turbidity, background.ground_albedo, sky_intensity);
+            },
+            (SkyModel::HosekWilkie, None) => return Err(SkyboxError::MissingKey { section: "background".to_string(), key: "hosek_wilkie_data".to_string() }),
+        }
+
+        let renderer = Renderer::new(CloudLayers::new(layers), background_, RenderParameters {
+            image_width: images.image_width,
+            image_height: images.image_height,
+            min_fog_dist: clouds.min_fog_dist,
+            max_fog_dist: clouds.max_fog_dist,
+            step_size: clouds.step_size,
+            step_count: clouds.step_count,
+            pixel_width: quality.pixel_width,
+            bundle_size: quality.bundle_size,
+            extinction: clouds.extinction,
+            direct_sun: clouds.direct_sun,
+            light_step_count: clouds.light_step_count,
+            light_step_size: clouds.light_step_size,
+            phase_function: clouds.phase_function,
+            seed: quality.renderer_seed,
+        });
+
+        Ok(Skybox {
+            renderer,
+            brightness: background.sun_brightness,
+            views,
+            layout: images.layout,
+            convention: images.convention,
+            image_width: images.image_width,
+            image_height: images.image_height,
+            thread_count,
+            tile_size: config.slices.tile_size,
+        })
+    }
+
+    /// The images selected by the configuration, in render order.
+    pub fn views(&self) -> &[View] {
+        &self.views
+    }
+
+    pub fn renderer(&self) -> &Renderer {
+        &self.renderer
+    }
+
+    pub fn layout(&self) -> CubemapLayout {
+        self.layout
+    }
+
+    /// Renders every selected view. With a cube map layout the faces are combined into a single image.
+    pub fn render(&self) -> Vec<FaceImage> {
+        self.render_with_progress(|_| {}, &CancellationToken::new()).expect("the render cannot be cancelled without a token")
+    }
+
+    pub fn render_with_progress<P: FnMut(&Progress)>(&self, on_progress: P, cancel: &CancellationToken) -> Result<Vec<FaceImage>, SkyboxError> {
+        let images = self.render_views_with_progress(&self.views, on_progress, cancel)?;
+
+        match self.layout {
+            CubemapLayout::Separate => Ok(images),
+            _ => Ok(vec![self.assemble(&images)]),
+        }
+    }
+
+    /// Combines six rendered faces according to the configured layout and convention.
+    pub fn assemble(&self, faces: &[FaceImage]) -> FaceImage {
+        self.layout.assemble(faces, self.convention)
+    }
+
+    pub fn render_face(&self, face: Face) -> FaceImage {
+        self.render_view(View::Face(face))
+    }
+
+    pub fn render_view(&self, view: View) -> FaceImage {
+        self.render_views(&[view]).remove(0)
+    }
+
+    /// Renders several views at once. The tiles of all views go through one shared queue, so every
+    /// worker stays busy until the last tile of the last view is done.
+    pub fn render_views(&self, views: &[View]) -> Vec<FaceImage> {
+        self.render_views_with_progress(views, |_| {}, &CancellationToken::new()).expect("the render cannot be cancelled without a token")
+    }
+
+    /// Like `render_views`, but calls `on_progress` after every finished tile and stops early once `cancel` is cancelled.
+    pub fn render_views_with_progress<P: FnMut(&Progress)>(&self, views: &[View], mut on_progress: P, cancel: &CancellationToken) -> Result<Vec<FaceImage>, SkyboxError> {
+        let now = Instant::now();
+        let cameras: Vec<Camera> = views.iter().map(|view| view.camera()).collect();
+        let tiles = make_tiles(views.len(), self.image_width, self.image_height, self.tile_size);
+        let tiles_per_view = tiles.len() / views.len().max(1);
+
+        let mut pixels = vec![vec![Color::new(0.0, 0.0, 0.0); self.image_width * self.image_height]; views.len()];
+        let mut progress = Progress {
+            completed_tiles: 0,
+            total_tiles: tiles.len(),
+            elapsed: Duration::ZERO,
+            views: views.iter().map(|view| ViewProgress { name: view.name(), completed_tiles: 0, total_tiles: tiles_per_view }).collect(),
+        };
+
+        let completed = render_tiles(
+            &tiles,
+            self.thread_count,
+            cancel,
+            |tile| self.renderer.render_tile(&cameras[tile.view_index], views[tile.view_index].id(), tile.x, tile.y, tile.width, tile.height),
+            |tile, tile_pixels| {
+                let view_pixels = &mut pixels[tile.view_index];
+                for (row, tile_row) in tile_pixels.chunks(tile.width).enumerate() {
+                    let i1 = (tile.y + row) * self.image_width + tile.x;
+                    view_pixels[i1..i1 + tile.width].copy_from_slice(tile_row);
+                }
+
+                progress.completed_tiles += 1;
+                progress.views[tile.view_index].completed_tiles += 1;
+                progress.elapsed = now.elapsed();
+                on_progress(&progress);
+            },
+        );
+
+        if !completed {
+            return Err(SkyboxError::Cancelled);
+        }
+
+        Ok(views.iter().zip(pixels).map(|(view, pixels)| FaceImage::new(view.name(), self.image_width, self.image_height, pixels, self.brightness)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small_config(tile_size: usize, thread_count: usize, use_multithreading: bool) -> SkyboxConfig {
+        let mut config = SkyboxConfig::default();
+        config.images.image_width = 16;
+        config.images.image_height = 16;
+        config.clouds.step_count = 4;
+        config.clouds.light_step_count = 2;
+        config.quality.bundle_size = 2;
+        config.slices.tile_size = tile_size;
+        config.slices.thread_count = thread_count;
+        config.slices.use_multithreading = use_multithreading;
+        config
+    }
+
+    fn bits(image: &FaceImage) -> Vec<[u64; 3]> {
+        image.pixels.iter().map(|color| color.to_array().map(f64::to_bits)).collect()
+    }
+
+    #[test]
+    fn output_does_not_depend_on_tiles_or_threads() {
+        let reference: Vec<_> = Skybox::new(&small_config(16, 1, false)).unwrap().render().iter().map(bits).collect();
+
+        for (tile_size, thread_count, use_multithreading) in [(1, 1, true), (5, 3, true), (7, 2, true), (16, 4, true), (3, 0, false)] {
+            let images: Vec<_> = Skybox::new(&small_config(tile_size, thread_count, use_multithreading)).unwrap().render().iter().map(bits).collect();
+
+            assert!(images == reference, "tile_size {}, thread_count {}, use_multithreading {}", tile_size, thread_count, use_multithreading);
+        }
+    }
+
+    #[test]
+    fn output_does_not_depend_on_rendered_faces() {
+        let skybox = Skybox::new(&small_config(5, 2, true)).unwrap();
+        let all = skybox.render_views(&Face::ALL.map(View::Face));
+
+        for (index, &face) in Face::ALL.iter().enumerate() {
+            assert!(bits(&skybox.render_face(face)) == bits(&all[index]), "face {}", face.name());
+        }
+
+        let subset = skybox.render_views(&[View::Face(Face::Up), View::Face(Face::West)]);
+        assert!(bits(&subset[0]) == bits(&all[Face::ALL.iter().position(|&face| face == Face::Up).unwrap()]));
+        assert!(bits(&subset[1]) == bits(&all[Face::ALL.iter().position(|&face| face == Face::West).unwrap()]));
+    }
+
+    #[test]
+    fn cancelling_from_the_progress_callback_stops_the_render() {
+        for thread_count in [1, 3] {
+            let skybox = Skybox::new(&small_config(4, thread_count, true)).unwrap();
+            let cancel = CancellationToken::new();
+            let mut reports: Vec<Progress> = Vec::new();
+
+            let result = skybox.render_with_progress(
+                |progress| {
+                    reports.push(progress.clone());
+                    if reports.len() == 5 {
+                        cancel.cancel();
+                    }
+                },
+                &cancel,
+            );
+
+            assert!(matches!(result, Err(SkyboxError::Cancelled)), "{} threads", thread_count);
+            // Without workers nothing is rendered after the cancellation. With them, only tiles that were already
+            // taken are still finished and reported.
+            if thread_count == 1 {
+                assert_eq!(reports.len(), 5);
+            }
+            assert!(reports.len() >= 5 && reports.len() < reports[0].total_tiles / 2, "{} reports with {} threads", reports.len(), thread_count);
+            for (index, progress) in reports.iter().enumerate() {
+                assert_eq!(progress.completed_tiles, index + 1);
+                assert!(progress.completed_tiles < progress.total_tiles);
+                assert_eq!(progress.views.iter().map(|view| view.completed_tiles).sum::<usize>(), progress.completed_tiles);
+                assert!(progress.views.iter().all(|view| view.completed_tiles <= view.total_tiles));
+            }
+        }
+    }
+}
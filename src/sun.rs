@@ -0,0 +1,257 @@
+// Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
+
+use crate::vec3::Vec3;
+
+/// How the sun direction is given, as chosen by the `mode` key of the `[sun]` section.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SunMode {
+    /// `sun_angle_phi` and `sun_angle_theta` from the `[background]` section.
+    Angles,
+    /// Computed from a place on Earth and a moment in time.
+    Location,
+}
+
+impl SunMode {
+    pub fn from_name(name: &str) -> Option<SunMode> {
+        match name {
+            "angles" => Some(SunMode::Angles),
+            "location" => Some(SunMode::Location),
+            _ => None,
+        }
+    }
+}
+
+/// Direction of `sun_angle_phi` radians from north (+Z), turned by `sun_angle_theta` radians from east (+X) toward up (+Y).
+pub fn direction_from_angles(phi: f64, theta: f64) -> Vec3 {
+    Vec3::new(phi.sin() * theta.cos(), phi.sin() * theta.sin(), phi.cos())
+}
+
+/// A calendar date and clock time in a time zone `utc_offset` hours ahead of UTC.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DateTime {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub utc_offset: f64,
+}
+
+impl DateTime {
+    /// Parses a `YYYY-MM-DD` date. The time is midnight UTC.
+    pub fn parse_date(date: &str) -> Option<DateTime> {
+        let mut parts = date.trim().splitn(3, '-');
+        let year = parts.next()?.parse().ok()?;
+        let month = parts.next()?.parse().ok()?;
+        let day = parts.next()?.parse().ok()?;
+
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return None;
+        }
+
+        Some(DateTime { year, month, day, hour: 0, minute: 0, second: 0, utc_offset: 0.0 })
+    }
+
+    /// Sets the clock time from `HH:MM` or `HH:MM:SS`.
+    pub fn with_time(self, time: &str) -> Option<DateTime> {
+        let mut parts = time.trim().splitn(3, ':');
+        let hour = parts.next()?.parse().ok()?;
+        let minute = parts.next()?.parse().ok()?;
+        let second = match parts.next() {
+            Some(second) => second.parse().ok()?,
+            None => 0,
+        };
+
+        if hour > 23 || minute > 59 || second > 59 {
+            return None;
+        }
+
+        Some(DateTime { hour, minute, second, ..self })
+    }
+
+    pub fn with_utc_offset(self, utc_offset: f64) -> DateTime {
+        DateTime { utc_offset, ..self }
+    }
+
+    /// Days since noon UTC on 1 January 4713 BC, with the algorithm from Meeus, "Astronomical Algorithms".
+    pub fn julian_day(&self) -> f64 {
+        let (mut year, mut month) = (self.year as f64, self.month as f64);
+        if self.month <= 2 {
+            year -= 1.0;
+            month += 12.0;
+        }
+
+        // Gregorian calendar correction.
+        let century = (year / 100.0).floor();
+        let correction = 2.0 - century + (century / 4.0).floor();
+
+        let hours = self.hour as f64 + self.minute as f64 / 60.0 + self.second as f64 / 3600.0 - self.utc_offset;
+
+        (365.25 * (year + 4716.0)).floor() + (30.6001 * (month + 1.0)).floor() + self.day as f64 + hours / 24.0 + correction - 1524.5
+    }
+}
+
+/// An observer on Earth. Latitude is positive to the north and longitude to the east, both in degrees.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SunLocation {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub date_time: DateTime,
+}
+
+impl SunLocation {
+    /// Sun elevation above the horizon and azimuth clockwise from north, in degrees. Uses the NOAA solar
+    /// calculator equations, which are accurate to about a hundredth of a degree between 1800 and 2100.
+    /// The position is geometric: atmospheric refraction is not added.
+    pub fn elevation_azimuth(&self) -> (f64, f64) {
+        let julian_century = (self.date_time.julian_day() - 2451545.0) / 36525.0;
+        let t = julian_century;
+
+        let mean_longitude = (280.46646 + t * (36000.76983 + t * 0.0003032)).rem_euclid(360.0);
+        let mean_anomaly = 357.52911 + t * (35999.05029 - 0.0001537 * t);
+        let eccentricity = 0.016708634 - t * (0.000042037 + 0.0000001267 * t);
+
+        let m = mean_anomaly.to_radians();
+        let center = m.sin() * (1.914602 - t * (0.004817 + 0.000014 * t)) + (2.0 * m).sin() * (0.019993 - 0.000101 * t) + (3.0 * m).sin() * 0.000289;
+
+        let omega = (125.04 - 1934.136 * t).to_radians();
+        let apparent_longitude = (mean_longitude + center - 0.00569 - 0.00478 * omega.sin()).to_radians();
+
+        let mean_obliquity = 23.0 + (26.0 + (21.448 - t * (46.815 + t * (0.00059 - t * 0.001813))) / 60.0) / 60.0;
+        let obliquity = (mean_obliquity + 0.00256 * omega.cos()).to_radians();
+
+        let declination = (obliquity.sin() * apparent_longitude.sin()).asin();
+
+        // Equation of time in minutes: how far the apparent sun runs ahead of the mean sun.
+        let l = mean_longitude.to_radians();
+        let y = (obliquity / 2.0).tan().powi(2);
+        let equation_of_time = 4.0 * (
+            y * (2.0 * l).sin()
+            - 2.0 * eccentricity * m.sin()
+            + 4.0 * eccentricity * y * m.sin() * (2.0 * l).cos()
+            - 0.5 * y * y * (4.0 * l).sin()
+            - 1.25 * eccentricity * eccentricity * (2.0 * m).sin()
+        ).to_degrees();
+
+        let date_time = &self.date_time;
+        let utc_minutes = (date_time.hour as f64 - date_time.utc_offset) * 60.0 + date_time.minute as f64 + date_time.second as f64 / 60.0;
+        let solar_minutes = (utc_minutes + equation_of_time + 4.0 * self.longitude).rem_euclid(1440.0);
+        let hour_angle = (solar_minutes / 4.0 - 180.0).to_radians();
+
+        self.horizontal(hour_angle, declination)
+    }
+
+    /// Elevation and azimuth in degrees of a body at the given hour angle and declination, in radians.
+    fn horizontal(&self, hour_angle: f64, declination: f64) -> (f64, f64) {
+        let latitude = self.latitude.to_radians();
+        let elevation = (latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos()).clamp(-1.0, 1.0).asin();
+        let azimuth = (-hour_angle.sin()).atan2(declination.tan() * latitude.cos() - latitude.sin() * hour_angle.cos());
+
+        (elevation.to_degrees(), azimuth.to_degrees().rem_euclid(360.0))
+    }
+
+    /// Local mean sidereal time in degrees, the right ascension that is due south (north in the southern hemisphere).
+    fn sidereal_time(&self) -> f64 {
+        let days = self.date_time.julian_day() - 2451545.0;
+        let t = days / 36525.0;
+
+        (280.46061837 + 360.98564736629 * days + 0.000387933 * t * t - t * t * t / 38710000.0 + self.longitude).rem_euclid(360.0)
+    }
+
+    /// Direction of a star or other body with the given right ascension and declination in degrees.
+    pub fn equatorial_direction(&self, right_ascension: f64, declination: f64) -> Vec3 {
+        let hour_angle = (self.sidereal_time() - right_ascension).to_radians();
+        let (elevation, azimuth) = self.horizontal(hour_angle, declination.to_radians());
+
+        horizontal_direction(elevation, azimuth)
+    }
+
+    /// Direction toward the moon, from the low precision formulae of the Astronomical Almanac (about 0.3 degrees).
+    /// Parallax is ignored, which can move the moon by up to a degree near the horizon.
+    pub fn moon_direction(&self) -> Vec3 {
+        let t = (self.date_time.julian_day() - 2451545.0) / 36525.0;
+        let sin = |degrees: f64| degrees.to_radians().sin();
+
+        let longitude = (218.32 + 481267.881 * t
+            + 6.29 * sin(135.0 + 477198.87 * t) - 1.27 * sin(259.3 - 413335.36 * t)
+            + 0.66 * sin(235.7 + 890534.22 * t) + 0.21 * sin(269.9 + 954397.74 * t)
+            - 0.19 * sin(357.5 + 35999.05 * t) - 0.11 * sin(186.5 + 966404.03 * t)).to_radians();
+        let latitude = (5.13 * sin(93.3 + 483202.02 * t) + 0.28 * sin(228.2 + 960400.89 * t)
+            - 0.28 * sin(318.3 + 6003.15 * t) - 0.17 * sin(217.6 - 407332.21 * t)).to_radians();
+        let obliquity = (23.439 - 0.013 * t).to_radians();
+
+        let right_ascension = (longitude.sin() * obliquity.cos() - latitude.tan() * obliquity.sin()).atan2(longitude.cos());
+        let declination = (latitude.sin() * obliquity.cos() + latitude.cos() * obliquity.sin() * longitude.sin()).asin();
+
+        self.equatorial_direction(right_ascension.to_degrees(), declination.to_degrees())
+    }
+
+    /// Direction toward the sun in skybox coordinates: north is +Z, east is +X and up is +Y.
+    pub fn direction(&self) -> Vec3 {
+        let (elevation, azimuth) = self.elevation_azimuth();
+
+        horizontal_direction(elevation, azimuth)
+    }
+}
+
+/// Converts an elevation and an azimuth clockwise from north, in degrees, to skybox coordinates.
+fn horizontal_direction(elevation: f64, azimuth: f64) -> Vec3 {
+    let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+
+    Vec3::new(elevation.cos() * azimuth.sin(), elevation.sin(), elevation.cos() * azimuth.cos())
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn julian_day_matches_meeus() {
+        // Examples 7.a and 7.b of Meeus, "Astronomical Algorithms".
+        let sputnik = DateTime::parse_date("1957-10-04").unwrap().with_time("19:26:24").unwrap();
+        assert!((sputnik.julian_day() - 2436116.31).abs() < 1e-6);
+        assert_eq!(DateTime::parse_date("2000-01-01").unwrap().with_time("12:00").unwrap().julian_day(), 2451545.0);
+    }
+
+    #[test]
+    fn sun_position_matches_published_values() {
+        // On 1992-10-13 at 0h the sun's apparent declination is -7.78507 degrees and the equation of time 13m42.6s
+        // (Meeus, "Astronomical Algorithms", examples 25.a and 28.a, which the NOAA equations are taken from). The
+        // expected positions follow from those, for observers at the sun's meridian and 45 degrees either side of it.
+        let midnight = DateTime::parse_date("1992-10-13").unwrap();
+
+        for (latitude, longitude, elevation, azimuth) in [
+            (0.0, 176.5725, 82.2149, 180.0),
+            (54.6872, 131.5725, 17.1236, 132.8543),
+            (-33.9, -123.4275, 29.1260, 280.8123),
+        ] {
+            let location = SunLocation { latitude, longitude, date_time: midnight };
+            let (computed_elevation, computed_azimuth) = location.elevation_azimuth();
+
+            assert!((computed_elevation - elevation).abs() < 0.01, "elevation at {}, {}: {} instead of {}", latitude, longitude, computed_elevation, elevation);
+            assert!((computed_azimuth - azimuth).abs() < 0.01, "azimuth at {}, {}: {} instead of {}", latitude, longitude, computed_azimuth, azimuth);
+        }
+
+        // The same moment in another time zone.
+        let location = SunLocation { latitude: 54.6872, longitude: 131.5725, date_time: DateTime::parse_date("1992-10-13").unwrap().with_time("03:00").unwrap().with_utc_offset(3.0) };
+        assert!((location.elevation_azimuth().0 - 17.1236).abs() < 0.01);
+    }
+
+    #[test]
+    fn direction_points_north_east_up() {
+        let direction = horizontal_direction(30.0, 90.0);
+        assert!((direction.x - 30.0f64.to_radians().cos()).abs() < 1e-12);
+        assert!((direction.y - 0.5).abs() < 1e-12);
+        assert!(direction.z.abs() < 1e-12);
+    }
+}
@@ -0,0 +1,55 @@
+// Copyright 2020-2022, Augustinas Lukauskas <augustinaslukauskas01@gmail.com>
+
+use crate::vec3::Vec3;
+use crate::camera::Camera;
+use crate::face::Face;
+
+/// How the sky is mapped onto output images.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Projection {
+    /// Six square images, one per cube face.
+    Cubemap,
+    /// A single 2:1 latitude-longitude panorama.
+    Equirectangular,
+}
+
+impl Projection {
+    pub fn from_name(name: &str) -> Option<Projection> {
+        match name {
+            "cubemap" => Some(Projection::Cubemap),
+            "equirectangular" => Some(Projection::Equirectangular),
+            _ => None,
+        }
+    }
+}
+
+/// A single output image of a skybox.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum View {
+    Face(Face),
+    Panorama,
+}
+
+impl View {
+    pub fn name(self) -> &'static str {
+        match self {
+            View::Face(face) => face.name(),
+            View::Panorama => "panorama",
+        }
+    }
+
+    /// A stable number identifying the view, used to key the random streams of its pixels.
+    pub fn id(self) -> u64 {
+        match self {
+            View::Face(face) => Face::ALL.iter().position(|&f| f == face).unwrap() as u64,
+            View::Panorama => Face::ALL.len() as u64,
+        }
+    }
+
+    pub fn camera(self) -> Camera {
+        match self {
+            View::Face(face) => face.camera(),
+            View::Panorama => Camera::new_equirectangular(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 1.0, 0.0)),
+        }
+    }
+}